@@ -18,7 +18,12 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 
 pub mod ardeck;
+pub mod control;
+pub mod error;
+pub mod headless;
 pub mod plugin;
 pub mod switch_info;
 pub mod settings;
+pub mod shutdown;
 pub mod action;
+pub mod token;
@@ -32,6 +32,10 @@ pub mod action_target;
 pub struct Action {
     pub switch: SwitchInfo,
     pub target: ActionTarget,
+    /// ActionResultで結果を返してもらうためのID
+    /// 結果を返せるプラグインへ送る時だけ付ける
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_id: Option<String>,
 }
 
 impl Action {
@@ -49,6 +53,7 @@ impl Action {
             actions.push(Action {
                 switch: switch.clone(),
                 target: t.clone(),
+                action_id: None,
             });
         }
 
@@ -60,8 +65,11 @@ impl Action {
     /// スイッチの情報から、そのスイッチが割り当てられているアクションを見つけ、Vec<ActionTarget>を返す
     async fn search_action_target_with_preset(preset_id: String, switch_info: SwitchInfo) -> Vec<ActionTarget> {
         let mapping_presets = match MappingPresetsJSON::new().load().await {
-            Some(presets) => presets,
-            None => return Vec::new(),  // TODO: Error
+            Ok(presets) => presets,
+            Err(e) => {
+                log::error!("Failed to load mapping presets: {}", e);
+                return Vec::new();
+            }
         };
         
         let mut target: Vec<ActionTarget> = Vec::new();
@@ -76,6 +84,7 @@ impl Action {
                     target.push(ActionTarget {
                         plugin_id: map.plugin_id.clone(),
                         action_id: map.action_id.clone(),
+                        parameters: map.parameters.clone(),
                     });
                 }
             }
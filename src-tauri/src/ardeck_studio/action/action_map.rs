@@ -18,7 +18,10 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use serde::{Deserialize, Serialize};
 
-use crate::ardeck_studio::switch_info::{SwitchId, SwitchType};
+use crate::ardeck_studio::{
+    plugin::parameter::ParameterValues,
+    switch_info::{SwitchId, SwitchType},
+};
 
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -32,4 +35,7 @@ pub struct ActionMap {
     pub plugin_id: String,
     // アクションのID
     pub action_id: String,
+    // アクションのパラメーター
+    #[serde(default)]
+    pub parameters: ParameterValues,
 }
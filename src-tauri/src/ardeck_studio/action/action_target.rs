@@ -18,9 +18,14 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use serde::{Deserialize, Serialize};
 
+use crate::ardeck_studio::plugin::parameter::ParameterValues;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ActionTarget {
     pub plugin_id: String,
     pub action_id: String,
+    /// マッピングに設定されたパラメーター
+    #[serde(default)]
+    pub parameters: ParameterValues,
 }
@@ -17,22 +17,33 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
 pub mod core;
+pub mod diagnose;
+pub mod error;
 pub mod manager;
+pub mod protocol;
 pub mod tauri;
+pub mod virtual_device;
 
 use log::trace;
 use serialport::{self, SerialPort, SerialPortInfo};
-use tauri::get_device_id;
 
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
 
-use tokio::sync::Mutex;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Mutex,
+};
 
 use crate::ardeck_studio::switch_info::ActionDataParser;
 
+use self::{core::get_device_id, error::OpenError};
+
+/// 読み取りの合間に書き込むまで溜めておけるフレームの数
+const WRITE_QUEUE_SIZE: usize = 32;
+
 #[derive(Clone)]
 pub struct Ardeck {
     continue_flag: Arc<Mutex<AtomicBool>>,
@@ -40,6 +51,10 @@ pub struct Ardeck {
     port: Arc<Mutex<Box<dyn SerialPort>>>,
     port_data: Arc<Mutex<ActionDataParser>>,
     device_id: String,
+
+    /// デバイスへ書き込むフレーム。読み取りのループが合間に書き込む
+    writes: mpsc::Sender<Vec<u8>>,
+    write_receiver: Arc<Mutex<Option<mpsc::Receiver<Vec<u8>>>>>,
 }
 
 /* State List
@@ -47,26 +62,25 @@ pub struct Ardeck {
 // - 1: Port Connecting.
 */
 
-pub enum OpenError {
-    Unknown,
-}
-
 impl Ardeck {
     pub fn open(port_info: SerialPortInfo, baud_rate: u32) -> Result<Ardeck, OpenError> {
         let port = serialport::new(&port_info.port_name, baud_rate).open();
 
         match port {
             Ok(port) => {
-                let device_id = get_device_id(port_info.clone()).unwrap();
+                let device_id = get_device_id(port_info.clone()).ok_or(OpenError::NoDeviceId)?;
                 log::debug!("Port Opened: {} {}", port_info.port_name, baud_rate);
+                let (writes, write_receiver) = mpsc::channel(WRITE_QUEUE_SIZE);
                 Ok(Ardeck {
                     continue_flag: Arc::new(Mutex::new(AtomicBool::new(true))),
                     port: Arc::new(Mutex::new(port)),
                     port_data: Arc::new(Mutex::new(ActionDataParser::new())),
                     device_id,
+                    writes,
+                    write_receiver: Arc::new(Mutex::new(Some(write_receiver))),
                 })
             }
-            Err(_) => Err(OpenError::Unknown),
+            Err(e) => Err(diagnose::classify(&port_info.port_name, e)),
         }
     }
 
@@ -92,6 +106,20 @@ impl Ardeck {
         Arc::clone(&self.port_data)
     }
 
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// 書き込むフレームを積む。読み取り中のポートのロックは待たない
+    pub fn queue_write(&self, frame: Vec<u8>) -> Result<(), TrySendError<Vec<u8>>> {
+        self.writes.try_send(frame)
+    }
+
+    /// 書き込むフレームの受け取り口を取り出す。読み取りのループだけが持つ
+    pub async fn take_write_receiver(&self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        self.write_receiver.lock().await.take()
+    }
+
     pub async fn close_request(&self) {
         self.continue_flag
             .lock()
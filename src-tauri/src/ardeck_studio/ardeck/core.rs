@@ -16,22 +16,526 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use super::{manager::ArdeckManager, Ardeck};
+use std::{collections::HashMap, io::Write, sync::Arc, time::Duration};
 
-pub struct ArdeckCore {
-    ardeck_manager: ArdeckManager,
+use once_cell::sync::Lazy;
+use serialport::{SerialPort, SerialPortInfo, SerialPortType};
+use tokio::sync::{broadcast, Mutex};
+
+use chrono::Utc;
+
+use crate::ardeck_studio::{
+    plugin::{self, manifest, subscription::SubscribedEvent},
+    switch_info::{SwitchId, SwitchInfo},
+};
+
+use super::{
+    error::DeviceError,
+    manager::ArdeckManager,
+    protocol,
+    virtual_device::{VirtualDevice, VirtualDeviceInfo},
+    Ardeck,
+};
+
+static ARDECK_MANAGER: Lazy<Mutex<ArdeckManager>> = Lazy::new(|| Mutex::new(ArdeckManager::new()));
+/// ポート名ごとの、プラグインが作った仮想デバイス
+static VIRTUAL_DEVICES: Lazy<Mutex<HashMap<String, VirtualDevice>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static ARDECK_EVENT: Lazy<broadcast::Sender<ArdeckEvent>> =
+    Lazy::new(|| broadcast::channel(100).0);
+
+/// デバイスマネージャーから通知されるイベント
+/// GUIではtauriのイベントへ、headlessではログへ流される
+#[derive(Clone, Debug)]
+pub enum ArdeckEvent {
+    /// 接続可能なポートの一覧が変化した
+    Ports(Vec<(String, SerialPortInfo)>),
+    /// ポートを開いた
+    Open(String),
+    /// ポートを閉じた
+    Close(String),
+    /// デバイスから1回分のデータを受信した
+    Message {
+        port_name: String,
+        device_id: String,
+        switch_info: SwitchInfo,
+    },
 }
 
+// SerialPortInfoからdevice_idを生成する
+pub fn get_device_id(port: SerialPortInfo) -> Option<String> {
+    match port.port_type.clone() {
+        SerialPortType::UsbPort(info) => {
+            if let Some(serial_number) = info.serial_number {
+                return Some(format!("{}-{}-{}", info.vid, info.pid, serial_number).to_string());
+            } else {
+                return Some(format!("{}-{}", info.vid, info.pid).to_string());
+            }
+        }
+        _ => return None,
+    }
+}
+
+fn get_port_info(port_name: &str) -> Result<SerialPortInfo, DeviceError> {
+    let ports = serialport::available_ports()?;
+    for port in ports {
+        if port.port_name == port_name {
+            return Ok(port);
+        }
+    }
+    Err(DeviceError::PortNotFound(port_name.to_string()))
+}
+
+/// tauriに依存しないデバイスマネージャー
+pub struct ArdeckCore;
+
 impl ArdeckCore {
-    pub fn new() -> Self {
-        Self {
-            ardeck_manager: ArdeckManager::new(),
+    /// デバイスマネージャーのイベントを購読する
+    pub fn subscribe() -> broadcast::Receiver<ArdeckEvent> {
+        ARDECK_EVENT.subscribe()
+    }
+
+    fn emit(event: ArdeckEvent) {
+        // 購読者がいない場合はErrになるが、問題はない
+        let _ = ARDECK_EVENT.send(event);
+    }
+
+    /// デバイスの接続と切断を、購読しているプラグインへ送る
+    fn publish_device(device_id: &str, port_name: &str, connected: bool) {
+        plugin::core::publish_event(SubscribedEvent::Device {
+            device_id: device_id.to_string(),
+            port_name: port_name.to_string(),
+            connected,
+        });
+    }
+
+    /// 現在接続中のポートの名前一覧を取得する
+    pub async fn get_connecting_serials() -> Vec<String> {
+        let serials = ARDECK_MANAGER.lock().await;
+
+        let keys = serials.keys();
+        keys.cloned().collect()
+    }
+
+    /// 現在接続中のデバイスの(ポート名, device_id)一覧を取得する
+    /// 仮想デバイスも含む
+    pub async fn get_connecting_devices() -> Vec<(String, String)> {
+        let mut devices: Vec<(String, String)> = ARDECK_MANAGER
+            .lock()
+            .await
+            .iter()
+            .map(|(port_name, ardeck)| (port_name.clone(), ardeck.device_id().to_string()))
+            .collect();
+
+        devices.extend(
+            VIRTUAL_DEVICES
+                .lock()
+                .await
+                .iter()
+                .map(|(port_name, device)| (port_name.clone(), device.device_id().to_string())),
+        );
+
+        devices
+    }
+
+    /// 接続中のデバイスのスイッチごとの最新の状態を取得する
+    pub async fn get_switch_states(port_name: &str) -> Result<Vec<SwitchInfo>, DeviceError> {
+        if let Some(device) = VIRTUAL_DEVICES.lock().await.get(port_name) {
+            return Ok(device.switch_states());
+        }
+
+        let port_data = match ARDECK_MANAGER.lock().await.get(port_name) {
+            Some(ardeck) => ardeck.port_data(),
+            None => return Err(DeviceError::NotOpened(port_name.to_string())),
+        };
+
+        let states = port_data.lock().await.switch_states();
+        Ok(states)
+    }
+
+    /// デバイスへLEDなどのフィードバックを送る
+    /// フレームは読み取りのループが次の読み取りの前に書き込む。書き込みの失敗はログに残す
+    pub async fn send_feedback(
+        port_name: &str,
+        switch_id: SwitchId,
+        value: u16,
+    ) -> Result<(), DeviceError> {
+        if VIRTUAL_DEVICES.lock().await.contains_key(port_name) {
+            return Err(DeviceError::Unsupported(port_name.to_string()));
+        }
+
+        let frame = protocol::feedback_frame(switch_id, value).to_vec();
+
+        match ARDECK_MANAGER.lock().await.get(port_name) {
+            Some(ardeck) => ardeck
+                .queue_write(frame)
+                .map_err(|_| DeviceError::WriteQueueFull(port_name.to_string())),
+            None => Err(DeviceError::NotOpened(port_name.to_string())),
+        }
+    }
+
+    /// プラグインの仮想デバイスを作る
+    /// local_idはプラグインの中で一意なID
+    pub async fn open_virtual(
+        plugin_id: &str,
+        local_id: &str,
+        name: Option<String>,
+    ) -> Result<VirtualDeviceInfo, DeviceError> {
+        if !manifest::is_valid_id(local_id) {
+            return Err(DeviceError::InvalidDeviceId(local_id.to_string()));
+        }
+
+        let device_id = VirtualDevice::device_id_for(plugin_id, local_id);
+        let port_name = VirtualDevice::port_name_for(&device_id);
+
+        let mut virtual_devices = VIRTUAL_DEVICES.lock().await;
+        if virtual_devices.contains_key(&port_name) {
+            return Err(DeviceError::AlreadyOpened(port_name));
+        }
+
+        let mut device = VirtualDevice::new(plugin_id, device_id.clone(), name);
+
+        // 物理デバイスと同じく、値が変わったスイッチをマッピングへ流す
+        let device_id_for_change = device_id.clone();
+        device.on_change_action(move |data| {
+            log::debug!(
+                "# VirtualDevice::on_change_action\n\tswitch_id: {}\n\tswitch_state: {}",
+                data.switch_id,
+                data.switch_state
+            );
+
+            let device_id = device_id_for_change.clone();
+            tokio::spawn(async move {
+                plugin::core::send_action_to_plugins(device_id, data).await;
+            });
+        });
+
+        let info = device.info();
+        virtual_devices.insert(port_name.clone(), device);
+        drop(virtual_devices);
+
+        Self::emit(ArdeckEvent::Open(port_name.clone()));
+        Self::publish_device(&device_id, &port_name, true);
+        log::info!("opened virtual device: {} ({})", port_name, plugin_id);
+
+        Ok(info)
+    }
+
+    /// 仮想デバイスのスイッチの状態を入れる
+    /// 物理デバイスから1回分のデータを受信した時と同じように扱う
+    pub async fn put_virtual_switch(
+        plugin_id: &str,
+        local_id: &str,
+        mut switch_info: SwitchInfo,
+    ) -> Result<(), DeviceError> {
+        let device_id = VirtualDevice::device_id_for(plugin_id, local_id);
+        let port_name = VirtualDevice::port_name_for(&device_id);
+
+        switch_info.set_timestamp(Utc::now().timestamp_millis());
+
+        match VIRTUAL_DEVICES.lock().await.get_mut(&port_name) {
+            Some(device) => device.put_switch(switch_info.clone()),
+            None => return Err(DeviceError::NotOpened(port_name)),
+        }
+
+        Self::emit(ArdeckEvent::Message {
+            port_name,
+            device_id,
+            switch_info,
+        });
+
+        Ok(())
+    }
+
+    /// 仮想デバイスを閉じる
+    pub async fn close_virtual(plugin_id: &str, local_id: &str) -> Result<(), DeviceError> {
+        let device_id = VirtualDevice::device_id_for(plugin_id, local_id);
+        let port_name = VirtualDevice::port_name_for(&device_id);
+
+        if VIRTUAL_DEVICES.lock().await.remove(&port_name).is_none() {
+            return Err(DeviceError::NotOpened(port_name));
+        }
+
+        Self::emit(ArdeckEvent::Close(port_name.clone()));
+        Self::publish_device(&device_id, &port_name, false);
+        log::info!("closed virtual device: {}", port_name);
+
+        Ok(())
+    }
+
+    /// プラグインが作った仮想デバイスを全て閉じる
+    /// セッションが終わった時に呼ぶ
+    pub async fn close_virtual_devices_of(plugin_id: &str) {
+        let closed: Vec<(String, String)> = {
+            let mut virtual_devices = VIRTUAL_DEVICES.lock().await;
+            let closed = virtual_devices
+                .iter()
+                .filter(|(_, device)| device.plugin_id() == plugin_id)
+                .map(|(port_name, device)| (port_name.clone(), device.device_id().to_string()))
+                .collect::<Vec<_>>();
+
+            for (port_name, _) in &closed {
+                virtual_devices.remove(port_name);
+            }
+            closed
+        };
+
+        for (port_name, device_id) in closed {
+            Self::emit(ArdeckEvent::Close(port_name.clone()));
+            Self::publish_device(&device_id, &port_name, false);
+            log::info!("closed virtual device: {}", port_name);
+        }
+    }
+
+    /// 仮想デバイスの一覧を取得する
+    pub async fn get_virtual_devices() -> Vec<VirtualDeviceInfo> {
+        VIRTUAL_DEVICES
+            .lock()
+            .await
+            .values()
+            .map(VirtualDevice::info)
+            .collect()
+    }
+
+    /// ポートの一覧をdevice_idとともに取得する
+    pub fn get_ports() -> Result<Vec<(String, SerialPortInfo)>, DeviceError> {
+        let ports = serialport::available_ports()?;
+        let mut list: Vec<(String, SerialPortInfo)> = Vec::new();
+
+        for port in ports {
+            match get_device_id(port.clone()) {
+                Some(device_id) => list.push((device_id, port)),
+                None => continue,
+            }
+        }
+
+        Ok(list)
+    }
+
+    async fn close(port_name: &str) {
+        let mut ardeck_manager = ARDECK_MANAGER.lock().await;
+        let closed = ardeck_manager.remove(port_name);
+        drop(ardeck_manager);
+
+        Self::emit(ArdeckEvent::Close(port_name.to_string()));
+        if let Some(ardeck) = closed {
+            Self::publish_device(ardeck.device_id(), port_name, false);
+        }
+
+        log::info!("closed: {}", port_name);
+    }
+
+    async fn get_port(port_name: &str) -> Result<Arc<Mutex<Box<dyn SerialPort>>>, DeviceError> {
+        let mut am = ARDECK_MANAGER.lock().await;
+        match am.get_mut(port_name) {
+            Some(a) => Ok(a.port().clone()),
+            None => Err(DeviceError::NotOpened(port_name.to_string())),
+        }
+    }
+
+    async fn start_read(port_name: &str) {
+        let port_name = port_name.to_string();
+        tokio::spawn(async move {
+            let port = match Self::get_port(port_name.as_str()).await {
+                Ok(port) => port,
+                Err(e) => {
+                    log::error!("Failed to start reading: {}", e);
+                    return;
+                }
+            };
+            let mut writes = match ARDECK_MANAGER.lock().await.get(&port_name) {
+                Some(ardeck) => ardeck.take_write_receiver().await,
+                None => None,
+            };
+            loop {
+                // 継続フラグがfalseならば切断する
+                let is_continue = match ARDECK_MANAGER.lock().await.get(&port_name) {
+                    Some(ardeck) => ardeck.is_continue().await,
+                    None => false,
+                };
+                if !is_continue {
+                    Self::close(&port_name).await;
+                    break;
+                }
+
+                // 読み取りの合間に、溜まったフレームを書き込む
+                if let Some(writes) = writes.as_mut() {
+                    while let Ok(frame) = writes.try_recv() {
+                        if let Err(e) = port.lock().await.write_all(&frame) {
+                            log::warn!("Failed to write to {}: {}", port_name, e);
+                        }
+                    }
+                }
+
+                let mut serial_buf: Vec<u8> = vec![0; 1];
+                let port = port.clone().lock().await.read(&mut serial_buf);
+                match port {
+                    Ok(_) => {
+                        let port_data = match ARDECK_MANAGER.lock().await.get(&port_name) {
+                            Some(ardeck) => ardeck.port_data(),
+                            None => break,
+                        };
+                        port_data.lock().await.put_data(serial_buf);
+                    }
+                    Err(kind) => {
+                        log::error!("Connection error. Connection stoped.\nKind: {}", kind);
+                        Self::close(&port_name).await;
+
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    /// 要求されたデバイスの処理継続フラグを折る
+    pub async fn close_request(port_name: &str) -> Result<(), DeviceError> {
+        match ARDECK_MANAGER.lock().await.get_mut(port_name) {
+            Some(a) => {
+                a.close_request().await;
+
+                Ok(())
+            }
+            None => Err(DeviceError::NotOpened(port_name.to_string())),
         }
     }
 
-    pub fn manager(&mut self) -> &mut ArdeckManager {
-        &mut self.ardeck_manager
+    /// 全てのデバイスの読み取りを止め、timeoutまで切断を待つ
+    pub async fn close_all(timeout: Duration) {
+        for port_name in Self::get_connecting_serials().await {
+            let _ = Self::close_request(&port_name).await;
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let remaining = Self::get_connecting_serials().await;
+            if remaining.is_empty() {
+                return;
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                log::warn!("Readers did not stop in time: {:?}", remaining);
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
     }
 
-    pub async fn start_read(ardeck: Ardeck) {}
+    /// デバイスへ接続し、受信データの読み取りを開始する
+    pub async fn open(port_name: &str, baud_rate: u32) -> Result<(), DeviceError> {
+        // 接続済みのポートならば何もしない
+        if ARDECK_MANAGER.lock().await.get(port_name).is_some() {
+            log::warn!("[{}] Already Opened.", port_name);
+            return Err(DeviceError::AlreadyOpened(port_name.to_string()));
+        }
+
+        // ポート情報を取得する
+        let port_info = get_port_info(port_name)?;
+
+        // デバイスへ接続する
+        let ardeck =
+            Ardeck::open(port_info.clone(), baud_rate).map_err(|source| DeviceError::Open {
+                port_name: port_name.to_string(),
+                source,
+            })?;
+
+        // 5秒間何も受け取れなければ通信を終了する
+        ardeck
+            .port()
+            .lock()
+            .await
+            .set_timeout(Duration::from_millis(5000))?;
+
+        // データを受信し、1回分のデータが完成した時の処理
+        let port_name_for_data = port_name.to_string();
+        let device_id_for_data = ardeck.device_id().to_string();
+        ardeck
+            .port_data()
+            .lock()
+            .await
+            .on_complete_action(move |data| {
+                log::trace!("# Ardeck::on_complete_action\n\tdata: {:#?}", data);
+
+                Self::emit(ArdeckEvent::Message {
+                    port_name: port_name_for_data.clone(),
+                    device_id: device_id_for_data.clone(),
+                    switch_info: data,
+                });
+            });
+
+        // TODO: async crosure
+        // 1回前のデータから値が変わったときの処理
+        let device_id_for_change = ardeck.device_id().to_string();
+        ardeck
+            .port_data()
+            .lock()
+            .await
+            .on_change_action(move |data| {
+                log::debug!(
+                    "# Ardeck::on_change_action\n\tswitch_id: {}\n\tswitch_state: {}",
+                    data.switch_id,
+                    data.switch_state
+                );
+
+                let device_id = device_id_for_change.clone();
+                tokio::spawn(async move {
+                    plugin::core::send_action_to_plugins(device_id, data.clone()).await;
+                });
+            });
+
+        // マネージャーにデバイスを追加
+        let device_id = ardeck.device_id().to_string();
+        ARDECK_MANAGER
+            .lock()
+            .await
+            .insert(port_name.to_string(), ardeck);
+
+        Self::emit(ArdeckEvent::Open(port_name.to_string()));
+        Self::publish_device(&device_id, port_name, true);
+
+        // 受信データの読み取り開始
+        Self::start_read(port_name).await;
+
+        Ok(())
+    }
+
+    /// ポートの一覧を監視し、変化があればイベントを発行する
+    pub fn serial_watch() {
+        let refresh_fps = 1000 / 4;
+        log::info!("Serial port watching: {}ms", refresh_fps);
+
+        tokio::spawn(async move {
+            let mut last_ports: Vec<SerialPortInfo> = Vec::new();
+
+            loop {
+                let ports = match serialport::available_ports() {
+                    Ok(ports) => ports,
+                    Err(e) => {
+                        log::error!("Failed to get ports: {}", e);
+                        tokio::time::sleep(Duration::from_millis(refresh_fps)).await;
+                        continue;
+                    }
+                };
+
+                if last_ports.clone() != ports.clone() {
+                    log::info!("Ports list changed: {:?}", ports);
+                    let mut payload: Vec<(String, SerialPortInfo)> = Vec::new();
+
+                    for port in ports.clone() {
+                        match get_device_id(port.clone()) {
+                            Some(device_id) => payload.push((device_id, port)),
+                            None => continue,
+                        }
+                    }
+
+                    Self::emit(ArdeckEvent::Ports(payload));
+                }
+
+                last_ports = ports;
+
+                tokio::time::sleep(Duration::from_millis(refresh_fps)).await;
+            }
+        });
+    }
 }
@@ -0,0 +1,216 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::io;
+
+use serialport::ErrorKind;
+
+use super::error::OpenError;
+
+/// serialport::Errorを分類し、ユーザーが取るべき対処を添える
+pub fn classify(port_name: &str, error: serialport::Error) -> OpenError {
+    match error.kind() {
+        ErrorKind::NoDevice | ErrorKind::Io(io::ErrorKind::NotFound) => {
+            OpenError::NoDevice { source: error }
+        }
+        ErrorKind::InvalidInput => OpenError::InvalidInput { source: error },
+        ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+            // Windowsでは、他のアプリがポートを使っているとアクセス拒否になる
+            if cfg!(windows) {
+                OpenError::Busy {
+                    source: error,
+                    hint: Some(
+                        "The port is used by another application (e.g. a serial monitor). Close it and try again."
+                            .to_string(),
+                    ),
+                }
+            } else {
+                OpenError::PermissionDenied {
+                    hint: permission_hint(port_name),
+                    source: error,
+                }
+            }
+        }
+        // EBUSYはErrorKind::Unknownとして返ってくる
+        ErrorKind::Unknown if error.description.to_lowercase().contains("busy") => {
+            OpenError::Busy {
+                hint: busy_hint(port_name),
+                source: error,
+            }
+        }
+        _ => OpenError::Unknown { source: error },
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn permission_hint(port_name: &str) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let gid = std::fs::metadata(port_name).ok()?.gid();
+    let group = linux::group_name(gid).unwrap_or_else(|| gid.to_string());
+
+    if linux::current_groups().contains(&gid) {
+        // グループには入っているが、ログインし直していない可能性がある
+        return Some(format!(
+            "You are in the '{}' group, but the session may be older than the change. Log out and log in again.",
+            group
+        ));
+    }
+
+    Some(format!(
+        "Your user is not in the '{}' group that owns {}. Run `sudo usermod -aG {} $USER`, then log out and log in again.",
+        group, port_name, group
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn permission_hint(_port_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn busy_hint(port_name: &str) -> Option<String> {
+    let holders = linux::port_holders(port_name);
+
+    if let Some(holder) = holders.iter().find(|h| h.name == linux::MODEM_MANAGER) {
+        return Some(modem_manager_hint(Some(holder.pid)));
+    }
+
+    if !holders.is_empty() {
+        let list = holders
+            .iter()
+            .map(|h| format!("{} (pid {})", h.name, h.pid))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return Some(format!("The port is locked by {}. Close it and try again.", list));
+    }
+
+    // 他のユーザーのプロセスのfdは読めないため、ModemManagerが動いているかだけ確認する
+    if linux::is_running(linux::MODEM_MANAGER) {
+        return Some(modem_manager_hint(None));
+    }
+
+    Some("The port is locked by another process. Close any serial monitor and try again.".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn busy_hint(_port_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn modem_manager_hint(pid: Option<u32>) -> String {
+    let who = match pid {
+        Some(pid) => format!("ModemManager (pid {}) has grabbed the port", pid),
+        None => "ModemManager is running and may have grabbed the port".to_string(),
+    };
+
+    format!(
+        "{}. Wait a few seconds and retry, stop it with `sudo systemctl stop ModemManager`, or add a udev rule with ENV{{ID_MM_DEVICE_IGNORE}}=\"1\" for this device.",
+        who
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{fs, path::Path};
+
+    pub const MODEM_MANAGER: &str = "ModemManager";
+
+    pub struct PortHolder {
+        pub pid: u32,
+        pub name: String,
+    }
+
+    /// /etc/groupからgidに対応するグループ名を取得する
+    pub fn group_name(gid: u32) -> Option<String> {
+        let groups = fs::read_to_string("/etc/group").ok()?;
+
+        groups.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse::<u32>().ok()?;
+
+            (id == gid).then(|| name.to_string())
+        })
+    }
+
+    /// このプロセスが所属するグループのgid一覧
+    pub fn current_groups() -> Vec<u32> {
+        let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Groups:"))
+            .map(|groups| {
+                groups
+                    .split_whitespace()
+                    .filter_map(|gid| gid.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn processes() -> Vec<u32> {
+        let dir = match fs::read_dir("/proc") {
+            Ok(dir) => dir,
+            Err(_) => return Vec::new(),
+        };
+
+        dir.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect()
+    }
+
+    fn process_name(pid: u32) -> String {
+        fs::read_to_string(format!("/proc/{}/comm", pid))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn is_running(name: &str) -> bool {
+        processes().into_iter().any(|pid| process_name(pid) == name)
+    }
+
+    /// /proc/[pid]/fdを調べ、ポートを開いているプロセスを見つける
+    pub fn port_holders(port_name: &str) -> Vec<PortHolder> {
+        let port = match Path::new(port_name).canonicalize() {
+            Ok(port) => port,
+            Err(_) => return Vec::new(),
+        };
+        let own_pid = std::process::id();
+
+        processes()
+            .into_iter()
+            .filter(|pid| *pid != own_pid)
+            .filter(|pid| {
+                fs::read_dir(format!("/proc/{}/fd", pid))
+                    .map(|fds| {
+                        fds.filter_map(|fd| fs::read_link(fd.ok()?.path()).ok())
+                            .any(|target| target == port)
+                    })
+                    .unwrap_or(false)
+            })
+            .map(|pid| PortHolder {
+                pid,
+                name: process_name(pid),
+            })
+            .collect()
+    }
+}
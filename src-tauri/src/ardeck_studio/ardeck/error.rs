@@ -0,0 +1,135 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::ardeck_studio::error::{serialize_error, ErrorCode};
+
+/// シリアルポートを開くときのエラー
+/// 原因が推測できる場合は、hintにユーザーが取るべき対処を入れる
+#[derive(Debug, Error)]
+pub enum OpenError {
+    #[error("The port is not a USB device")]
+    NoDeviceId,
+    #[error("The device is not connected")]
+    NoDevice {
+        #[source]
+        source: serialport::Error,
+    },
+    #[error("Invalid port settings")]
+    InvalidInput {
+        #[source]
+        source: serialport::Error,
+    },
+    #[error("Permission denied")]
+    PermissionDenied {
+        #[source]
+        source: serialport::Error,
+        hint: Option<String>,
+    },
+    #[error("The port is busy")]
+    Busy {
+        #[source]
+        source: serialport::Error,
+        hint: Option<String>,
+    },
+    #[error("Unknown error")]
+    Unknown {
+        #[source]
+        source: serialport::Error,
+    },
+}
+
+impl OpenError {
+    pub fn hint(&self) -> Option<&str> {
+        match self {
+            Self::PermissionDenied { hint, .. } | Self::Busy { hint, .. } => hint.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// デバイスマネージャーのエラー
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("Port not found: {0}")]
+    PortNotFound(String),
+    #[error("Already opened: {0}")]
+    AlreadyOpened(String),
+    #[error("Not opened: {0}")]
+    NotOpened(String),
+    #[error("Invalid device id: {0}")]
+    InvalidDeviceId(String),
+    #[error("Not supported by the device: {0}")]
+    Unsupported(String),
+    #[error("Too many pending writes: {0}")]
+    WriteQueueFull(String),
+    #[error("Failed to open {port_name}: {source}")]
+    Open {
+        port_name: String,
+        #[source]
+        source: OpenError,
+    },
+    #[error("Serial port error")]
+    Serial(#[from] serialport::Error),
+}
+
+impl ErrorCode for DeviceError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PortNotFound(_) => "device.port_not_found",
+            Self::AlreadyOpened(_) => "device.already_opened",
+            Self::NotOpened(_) => "device.not_opened",
+            Self::InvalidDeviceId(_) => "device.invalid_device_id",
+            Self::Unsupported(_) => "device.unsupported",
+            Self::WriteQueueFull(_) => "device.write_queue_full",
+            Self::Open { source, .. } => match source {
+                OpenError::NoDeviceId => "device.not_usb",
+                OpenError::NoDevice { .. } => "device.no_device",
+                OpenError::InvalidInput { .. } => "device.invalid_settings",
+                OpenError::PermissionDenied { .. } => "device.permission_denied",
+                OpenError::Busy { .. } => "device.busy",
+                OpenError::Unknown { .. } => "device.open_failed",
+            },
+            Self::Serial(_) => "device.serial",
+        }
+    }
+
+    fn details(&self) -> Option<String> {
+        match self {
+            // シリアルポートのエラーと、対処方法を返す
+            Self::Open { source, .. } => {
+                let cause = std::error::Error::source(source).map(|e| e.to_string());
+
+                match (cause, source.hint()) {
+                    (Some(cause), Some(hint)) => Some(format!("{}\n{}", cause, hint)),
+                    (cause, hint) => cause.or(hint.map(|h| h.to_string())),
+                }
+            }
+            _ => std::error::Error::source(self).map(|e| e.to_string()),
+        }
+    }
+}
+
+impl Serialize for DeviceError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
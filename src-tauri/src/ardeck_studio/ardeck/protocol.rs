@@ -0,0 +1,56 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use crate::ardeck_studio::switch_info::SwitchId;
+
+/// ホストからデバイスへ送るフィードバックのフレーム
+///
+/// | offset | size | 内容 |
+/// |---|---|---|
+/// | 0 | 4 | ヘッダー `"ADFB"` |
+/// | 4 | 1 | スイッチID |
+/// | 5 | 2 | 値 (u16, ビッグエンディアン) |
+///
+/// デバイスからの入力はActionDataParserが読む`"ADEC"`のフレームで、向きが逆のため別のヘッダーにしている
+/// ファームウェアはこのヘッダー以外のバイト列を読み捨てること
+pub const FEEDBACK_HEADER: &[u8; 4] = b"ADFB";
+/// フィードバックのフレームの長さ
+pub const FEEDBACK_FRAME_LEN: usize = FEEDBACK_HEADER.len() + 1 + 2;
+
+/// フィードバックのフレームを作る
+pub fn feedback_frame(switch_id: SwitchId, value: u16) -> [u8; FEEDBACK_FRAME_LEN] {
+    let mut frame = [0; FEEDBACK_FRAME_LEN];
+    frame[..4].copy_from_slice(FEEDBACK_HEADER);
+    frame[4] = switch_id;
+    frame[5..].copy_from_slice(&value.to_be_bytes());
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feedback_frame_layout() {
+        assert_eq!(
+            feedback_frame(3, 0x0102),
+            [b'A', b'D', b'F', b'B', 3, 0x01, 0x02]
+        );
+    }
+}
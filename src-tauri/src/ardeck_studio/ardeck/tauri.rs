@@ -16,268 +16,102 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use once_cell::sync::Lazy;
-use serialport::{SerialPort, SerialPortInfo, SerialPortType};
-use std::{io, sync::Arc, time::Duration};
+
 use tauri::{
     plugin::{Builder, TauriPlugin},
     Manager, Runtime,
 };
-use tokio::sync::Mutex;
-
-use crate::ardeck_studio::plugin;
-
-use super::{manager::ArdeckManager, Ardeck};
+use tokio::sync::broadcast::error::RecvError;
 
-static ARDECK_MANAGER: Lazy<Mutex<ArdeckManager>> = Lazy::new(|| Mutex::new(ArdeckManager::new()));
-// static ACTION_MANAGER: Lazy<Mutex<ArdeckManager>> = Lazy::new(|| Mutex::new(ActionManager::new()));
+use crate::ardeck_studio::error::ErrorCode;
 
-// SerialPortInfoからdevice_idを生成する
-pub fn get_device_id(port: SerialPortInfo) -> Option<String> {
-    match port.port_type.clone() {
-        SerialPortType::UsbPort(info) => {
-            if let Some(serial_number) = info.serial_number {
-                return Some(format!("{}-{}-{}", info.vid, info.pid, serial_number).to_string());
-            } else {
-                return Some(format!("{}-{}", info.vid, info.pid).to_string());
-            }
-        }
-        _ => return None,
-    }
-}
-
-fn get_port_info(port_name: &str) -> io::Result<SerialPortInfo> {
-    let ports = serialport::available_ports()?;
-    for port in ports {
-        if port.port_name == port_name {
-            return Ok(port);
-        }
-    }
-    Err(io::Error::new(io::ErrorKind::NotFound, "error"))
-}
+use super::{
+    core::{ArdeckCore, ArdeckEvent},
+    error::DeviceError,
+    virtual_device::VirtualDeviceInfo,
+};
 
 // 現在接続中のポートの名前一覧を取得する
 // invoke("plugin:ardeck|get_connecting_serials");
 #[tauri::command]
 async fn get_connecting_serials() -> Vec<String> {
-    let serials = ARDECK_MANAGER.lock().await;
-
-    let keys = serials.keys();
-    keys.cloned().collect()
-}
-
-async fn close<R: Runtime>(app: tauri::AppHandle<R>, port_name: &str) {
-    let mut ardeck_manager = ARDECK_MANAGER.lock().await;
-    ardeck_manager.remove(port_name);
-
-    // TODO: DELETE
-    app.emit_all("on-close-serial", port_name).unwrap();
-
-    log::info!("closed: {}", port_name);
-}
-
-async fn get_port(port_name: &str) -> io::Result<Arc<Mutex<Box<dyn SerialPort>>>> {
-    let mut am = ARDECK_MANAGER.lock().await;
-    match am.get_mut(port_name) {
-        Some(a) => Ok(a.port().clone()),
-        None => Err(io::Error::new(io::ErrorKind::NotFound, "error")),
-    }
-}
-
-async fn port_read<R: Runtime>(app: tauri::AppHandle<R>, port_name: &str) {
-    let port_name = port_name.to_string();
-    tokio::spawn(async move {
-        let port = get_port(port_name.as_str()).await.unwrap();
-        loop {
-            // 継続フラグがfalseならば切断する
-            if !ARDECK_MANAGER
-                .lock()
-                .await
-                .get(&port_name)
-                .unwrap()
-                .is_continue()
-                .await
-            {
-                // drop(am);
-                close(app.app_handle(), &port_name).await;
-                break;
-            }
-
-            let mut serial_buf: Vec<u8> = vec![0; 1];
-            let port = port.clone().lock().await.read(&mut serial_buf);
-            // let try_read = port;
-            match port {
-                Ok(_) => {
-                    let port_data = ARDECK_MANAGER
-                        .lock()
-                        .await
-                        .get(&port_name)
-                        .unwrap()
-                        .port_data();
-                    port_data.lock().await.put_data(serial_buf);
-                }
-                Err(kind) => {
-                    log::error!("Connection error. Connection stoped.\nKind: {}", kind);
-                    close(app.app_handle(), &port_name).await;
-
-                    break;
-                }
-            }
-        }
-    });
+    ArdeckCore::get_connecting_serials().await
 }
 
 // invoke("plugin:ardeck|close_port");
 #[tauri::command]
-async fn close_port<R: Runtime>(_app: tauri::AppHandle<R>, port_name: &str) -> Result<(), u32> {
+async fn close_port<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    port_name: &str,
+) -> Result<(), DeviceError> {
     log::info!("Ardeck Disconnect Request: {}", port_name);
-    // 要求されたデバイスの処理継続フラグを折る
-    match ARDECK_MANAGER.lock().await.get_mut(port_name) {
-        Some(a) => {
-            a.close_request().await;
 
-            return Ok(());
-        }
-        None => {
-            log::error!("[{}] Not closed.", port_name);
-            return Err(501);
-        }
-    };
+    ArdeckCore::close_request(port_name)
+        .await
+        .inspect_err(|_| log::error!("[{}] Not closed.", port_name))
 }
 
 // invoke("plugin:ardeck|open_port");
 #[tauri::command]
 async fn open_port<R: Runtime>(
-    // app: tauri::AppHandle
-    app: tauri::AppHandle<R>,
+    _app: tauri::AppHandle<R>,
     port_name: &str,
     baud_rate: u32,
-) -> Result<(), u32> {
-    // print!("\x1B[2J\x1B[1;1H"); // ! コンソールをクリア
+) -> Result<(), DeviceError> {
     log::info!("Ardeck Connect Request: {}", port_name);
-    // 接続済みのポートならば何もしない
-    if ARDECK_MANAGER.lock().await.get(port_name).is_some() {
-        log::warn!("[{}] Already Opened.", port_name);
-        return Err(501);
-    }
 
-    // ポート情報を取得する
-    let port_info = get_port_info(port_name).unwrap();
+    ArdeckCore::open(port_name, baud_rate).await.map_err(|e| {
+        log::error!("Open Error: {}: {}", port_name, e);
 
-    // デバイスへ接続する
-    let ardeck = match Ardeck::open(port_info.clone(), baud_rate) {
-        Ok(f) => f,
-        Err(_e) => {
-            log::error!("Open Error: {}", port_name);
-
-            return Err(500);
+        if let Some(details) = e.details() {
+            log::error!("{}", details);
         }
-    };
-
-    // 5秒間何も受け取れなければ通信を終了する
-    ardeck
-        .port()
-        .lock()
-        .await
-        .set_timeout(Duration::from_millis(5000))
-        .unwrap();
-
-    let app_for_data = app.app_handle();
-
-    // データを受信し、1回分のデータが完成した時の処理
-    ardeck
-        .port_data()
-        .lock()
-        .await
-        .on_complete_action(move |data| {
-            log::trace!("# Ardeck::on_complete_action\n\tdata: {:#?}", data);
-
-            app_for_data
-                .emit_all("on-message-serial", data.clone())
-                .unwrap();
-        });
-
-    // TODO: async crosure
-    // 1回前のデータから値が変わったときの処理
-    let port_info_clone = port_info.clone();
-    ardeck
-        .port_data()
-        .lock()
-        .await
-        .on_change_action(move |data| {
-            log::debug!(
-                "# Ardeck::on_change_action\n\tswitch_id: {}\n\tswitch_state: {}",
-                data.switch_id,
-                data.switch_state
-            );
-
-            let port_info_clone = port_info_clone.clone();
-            tokio::spawn(async move {
-                plugin::tauri::send_action_to_plugins(port_info_clone, data.clone()).await;
-            });
-        });
-
-    // マネージャーにデバイスを追加
-    ARDECK_MANAGER
-        .lock()
-        .await
-        .insert(port_name.to_string(), ardeck);
-
-    app.emit_all("on-open-serial", port_name).unwrap();
+        e
+    })
+}
 
-    // 受信データの読み取り開始
-    port_read(app.app_handle(), port_name).await;
+// プラグインが作った仮想デバイスの一覧を取得する
+// invoke("plugin:ardeck|get_virtual_devices");
+#[tauri::command]
+async fn get_virtual_devices() -> Vec<VirtualDeviceInfo> {
+    ArdeckCore::get_virtual_devices().await
+}
 
-    Ok(())
+// ポートの一覧を取得する
+#[tauri::command]
+fn get_ports() -> Result<Vec<(String, serialport::SerialPortInfo)>, DeviceError> {
+    ArdeckCore::get_ports()
 }
 
-fn serial_watch<R: Runtime>(tauri_app: tauri::AppHandle<R>) {
-    let refresh_fps = 1000 / 4;
-    log::info!("Serial port watching: {}ms", refresh_fps);
+// デバイスマネージャーのイベントをフロントエンドへ転送する
+fn forward_events<R: Runtime>(tauri_app: tauri::AppHandle<R>) {
+    let mut events = ArdeckCore::subscribe();
 
     tokio::spawn(async move {
-        let mut last_ports: Vec<SerialPortInfo> = Vec::new();
-
         loop {
-            let ports = serialport::available_ports().unwrap();
-
-            if last_ports.clone() != ports.clone() {
-                log::info!("Ports list changed: {:?}", ports);
-                let mut payload: Vec<(String, SerialPortInfo)> = Vec::new();
-
-                for port in ports.clone() {
-                    match get_device_id(port.clone()) {
-                        Some(device_id) => payload.push((device_id, port)),
-                        None => continue,
-                    }
+            let result = match events.recv().await {
+                Ok(ArdeckEvent::Ports(payload)) => tauri_app.emit_all("on-ports", payload),
+                Ok(ArdeckEvent::Open(port_name)) => tauri_app.emit_all("on-open-serial", port_name),
+                Ok(ArdeckEvent::Close(port_name)) => {
+                    tauri_app.emit_all("on-close-serial", port_name)
+                }
+                Ok(ArdeckEvent::Message { switch_info, .. }) => {
+                    tauri_app.emit_all("on-message-serial", switch_info)
                 }
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Ardeck event lagged: {} events skipped", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
 
-                tauri_app.emit_all("on-ports", payload).unwrap();
+            if let Err(e) = result {
+                log::error!("Failed to emit ardeck event: {}", e);
             }
-
-            last_ports = ports;
-
-            tokio::time::sleep(Duration::from_millis(refresh_fps)).await;
         }
     });
 }
 
-// ポートの一覧を取得する
-#[tauri::command]
-fn get_ports() -> Vec<(String, serialport::SerialPortInfo)> {
-    let ports = serialport::available_ports().unwrap();
-    let mut list: Vec<(String, serialport::SerialPortInfo)> = Vec::new();
-
-    for port in ports {
-        match get_device_id(port.clone()) {
-            Some(device_id) => list.push((device_id, port)),
-            None => continue,
-        }
-    }
-
-    list
-}
-
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
     log::info!("Initializing Ardeck Tauri Plugin");
 
@@ -286,11 +120,12 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
             open_port,
             close_port,
             get_connecting_serials,
-            get_ports
+            get_ports,
+            get_virtual_devices
         ])
         .setup(|app| {
-            serial_watch(app.app_handle());
-            // app.manage(Mutex::new(ArdeckManager::new()));
+            forward_events(app.app_handle());
+            ArdeckCore::serial_watch();
             Ok(())
         })
         .build()
@@ -0,0 +1,97 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde::Serialize;
+
+use crate::ardeck_studio::switch_info::{compare::ActionCompare, SwitchInfo};
+
+/// 仮想デバイスのポート名の接頭辞
+pub const VIRTUAL_PORT_PREFIX: &str = "virtual:";
+
+/// プラグインが作る仮想の入力デバイス
+/// 物理デバイスと同じく、スイッチの変化をActionCompareで検出してマッピングへ流す
+pub struct VirtualDevice {
+    device_id: String,
+    name: Option<String>,
+    /// デバイスを作ったプラグイン
+    plugin_id: String,
+    compare: ActionCompare,
+}
+
+/// フロントエンドやプラグインへ返す仮想デバイスの情報
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualDeviceInfo {
+    pub port_name: String,
+    pub device_id: String,
+    pub name: Option<String>,
+    pub plugin_id: String,
+}
+
+impl VirtualDevice {
+    pub fn new(plugin_id: &str, device_id: String, name: Option<String>) -> Self {
+        Self {
+            device_id,
+            name,
+            plugin_id: plugin_id.to_string(),
+            compare: ActionCompare::new(),
+        }
+    }
+
+    /// プラグインごとのIDから、他のデバイスと重ならないdevice_idを作る
+    pub fn device_id_for(plugin_id: &str, local_id: &str) -> String {
+        format!("{}:{}", plugin_id, local_id)
+    }
+
+    pub fn port_name_for(device_id: &str) -> String {
+        format!("{}{}", VIRTUAL_PORT_PREFIX, device_id)
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn plugin_id(&self) -> &str {
+        &self.plugin_id
+    }
+
+    pub fn info(&self) -> VirtualDeviceInfo {
+        VirtualDeviceInfo {
+            port_name: Self::port_name_for(&self.device_id),
+            device_id: self.device_id.clone(),
+            name: self.name.clone(),
+            plugin_id: self.plugin_id.clone(),
+        }
+    }
+
+    /// プラグインから送られたスイッチの状態を入れる
+    pub fn put_switch(&mut self, switch_info: SwitchInfo) {
+        self.compare.put_action(switch_info);
+    }
+
+    /// スイッチの状態が変わったときに実行する処理を登録する
+    pub fn on_change_action<F: Fn(SwitchInfo) + Send + 'static>(&mut self, callback: F) {
+        self.compare.on_change_action(callback);
+    }
+
+    /// スイッチごとの最新の状態を取得する
+    pub fn switch_states(&self) -> Vec<SwitchInfo> {
+        self.compare.states()
+    }
+}
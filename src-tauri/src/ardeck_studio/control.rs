@@ -0,0 +1,399 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::broadcast::error::RecvError,
+};
+use uuid::Uuid;
+
+use super::{
+    ardeck::core::{ArdeckCore, ArdeckEvent},
+    error::ErrorCode,
+    settings,
+    switch_info::SwitchInfo,
+    token,
+};
+use crate::service::dir::Directories;
+
+/// コントロールソケットのアドレス
+/// ardeckctlなど、外部のツールから起動中のスタジオを操作するために使う
+pub const CONTROL_ADDR: &str = "127.0.0.1:6726";
+
+/// 起動ごとのトークンを書き出すファイル名。設定ディレクトリに置き、本人だけが読める
+const CONTROL_TOKEN_FILE: &str = "control.token";
+
+/// このプロセスが書き出したトークンファイル。他のインスタンスのものは消さない
+static TOKEN_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// コントロールソケットの起動のエラー
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("Control port {0} is already in use. Another ardeck studio may be running")]
+    AddrInUse(String),
+    #[error("Failed to listen on {addr}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to write control token: {}", path.display())]
+    Token {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl ErrorCode for ControlError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::AddrInUse(_) => "control.addr_in_use",
+            Self::Bind { .. } => "control.bind",
+            Self::Token { .. } => "control.token",
+        }
+    }
+}
+
+/// ardeckctlからスタジオへの要求
+/// 1行に1つのJSONとして送られる
+/// 最初の要求はAuthでなければならない
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", tag = "op", content = "data")]
+pub enum ControlRequest {
+    /// トークンファイルの内容で認証する
+    Auth { token: String },
+    GetConnectingSerials,
+    #[serde(rename_all = "camelCase")]
+    OpenPort { port_name: String, baud_rate: u32 },
+    #[serde(rename_all = "camelCase")]
+    ClosePort { port_name: String },
+    /// 以降、デバイスから受信したスイッチの情報を送り続ける
+    Subscribe,
+    GetMappingList,
+    #[serde(rename_all = "camelCase")]
+    SetMappingPreset { device_id: String, preset_id: String },
+}
+
+/// スタジオからardeckctlへの応答
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", tag = "op", content = "data")]
+pub enum ControlResponse {
+    Ok,
+    Error(String),
+    ConnectingSerials(Vec<String>),
+    MappingList(Vec<(String, String)>),
+    Switch(ControlSwitchEvent),
+}
+
+impl ControlResponse {
+    /// エラーを、原因も含めた1行のメッセージにする
+    fn error<E: ErrorCode>(error: &E) -> Self {
+        match error.details() {
+            Some(details) => Self::Error(format!("{} ({}): {}", error, error.code(), details)),
+            None => Self::Error(format!("{} ({})", error, error.code())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlSwitchEvent {
+    pub port_name: String,
+    pub device_id: String,
+    pub switch: SwitchInfo,
+}
+
+/// コントロールソケットの待ち受けを開始する
+/// 同じマシンの他のユーザーに操作されないよう、起動ごとのトークンをファイルに書き出し、接続時に確かめる
+pub async fn start() -> Result<(), ControlError> {
+    let tcp = TcpListener::bind(CONTROL_ADDR).await.map_err(|source| {
+        if source.kind() == io::ErrorKind::AddrInUse {
+            ControlError::AddrInUse(CONTROL_ADDR.to_string())
+        } else {
+            ControlError::Bind {
+                addr: CONTROL_ADDR.to_string(),
+                source,
+            }
+        }
+    })?;
+
+    let token = Uuid::new_v4().simple().to_string();
+    let path = token_path().map_err(|source| ControlError::Token {
+        path: PathBuf::from(CONTROL_TOKEN_FILE),
+        source,
+    })?;
+    if let Err(source) = write_token(&path, &token) {
+        return Err(ControlError::Token { path, source });
+    }
+    let _ = TOKEN_FILE.set(path);
+    let token = Arc::new(token);
+
+    log::info!("Control server listening on {}", CONTROL_ADDR);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match tcp.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept control connection: {}", e);
+                    continue;
+                }
+            };
+            log::debug!("Control client connected: {}", peer);
+
+            let token = Arc::clone(&token);
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &token).await {
+                    log::debug!("Control client disconnected: {}: {}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// 終了時にトークンファイルを消す
+pub fn remove_token() {
+    if let Some(path) = TOKEN_FILE.get() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn token_path() -> io::Result<PathBuf> {
+    Ok(Directories::get_confing_dir()?.join(CONTROL_TOKEN_FILE))
+}
+
+/// 本人だけが読めるファイルとしてトークンを書き出す
+/// 前回のファイルは権限ごと作り直す
+fn write_token(path: &Path, token: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(path) {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(token.as_bytes())
+}
+
+async fn handle_connection(stream: TcpStream, token: &str) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // 最初の要求で認証できなければ切断する
+    let authorized = match lines.next_line().await? {
+        Some(line) => matches!(
+            serde_json::from_str(&line),
+            Ok(ControlRequest::Auth { token: actual }) if token::verify(token, &actual)
+        ),
+        None => return Ok(()),
+    };
+    if !authorized {
+        log::warn!("Control client rejected: invalid token");
+        write_response(&mut writer, &ControlResponse::Error("Unauthorized".to_string())).await?;
+        return Ok(());
+    }
+    write_response(&mut writer, &ControlResponse::Ok).await?;
+
+    while let Some(line) = lines.next_line().await? {
+        let request: ControlRequest = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                write_response(&mut writer, &ControlResponse::Error(e.to_string())).await?;
+                continue;
+            }
+        };
+
+        log::debug!("Control request: {:?}", request);
+
+        let response = match request {
+            ControlRequest::Auth { .. } => ControlResponse::Ok,
+            ControlRequest::GetConnectingSerials => {
+                ControlResponse::ConnectingSerials(ArdeckCore::get_connecting_serials().await)
+            }
+            ControlRequest::OpenPort {
+                port_name,
+                baud_rate,
+            } => match ArdeckCore::open(&port_name, baud_rate).await {
+                Ok(_) => ControlResponse::Ok,
+                Err(e) => ControlResponse::error(&e),
+            },
+            ControlRequest::ClosePort { port_name } => {
+                match ArdeckCore::close_request(&port_name).await {
+                    Ok(_) => ControlResponse::Ok,
+                    Err(e) => ControlResponse::error(&e),
+                }
+            }
+            ControlRequest::Subscribe => {
+                write_response(&mut writer, &ControlResponse::Ok).await?;
+                return stream_switch_events(writer).await;
+            }
+            ControlRequest::GetMappingList => match settings::core::get_mapping_list().await {
+                Ok(list) => ControlResponse::MappingList(list),
+                Err(e) => ControlResponse::error(&e),
+            },
+            ControlRequest::SetMappingPreset {
+                device_id,
+                preset_id,
+            } => match settings::core::set_mapping_preset(&device_id, &preset_id).await {
+                Ok(_) => ControlResponse::Ok,
+                Err(e) => ControlResponse::error(&e),
+            },
+        };
+
+        write_response(&mut writer, &response).await?;
+    }
+
+    Ok(())
+}
+
+// 接続が切れるまで、スイッチの情報を送り続ける
+async fn stream_switch_events(mut writer: OwnedWriteHalf) -> io::Result<()> {
+    let mut events = ArdeckCore::subscribe();
+
+    loop {
+        match events.recv().await {
+            Ok(ArdeckEvent::Message {
+                port_name,
+                device_id,
+                switch_info,
+            }) => {
+                let event = ControlSwitchEvent {
+                    port_name,
+                    device_id,
+                    switch: switch_info,
+                };
+                write_response(&mut writer, &ControlResponse::Switch(event)).await?;
+            }
+            Ok(_) => (),
+            Err(RecvError::Lagged(n)) => log::warn!("Control event lagged: {} events skipped", n),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn write_response(writer: &mut OwnedWriteHalf, response: &ControlResponse) -> io::Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+/// コントロールソケットのクライアント
+pub struct ControlClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl ControlClient {
+    /// スタジオが書き出したトークンを読み、接続して認証する
+    pub async fn connect() -> io::Result<Self> {
+        let path = token_path()?;
+        let token = fs::read_to_string(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read control token {}: {}", path.display(), e),
+            )
+        })?;
+
+        let (reader, writer) = TcpStream::connect(CONTROL_ADDR).await?.into_split();
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        let auth = ControlRequest::Auth {
+            token: token.trim().to_string(),
+        };
+        match client.request(&auth).await? {
+            ControlResponse::Ok => Ok(client),
+            ControlResponse::Error(e) => Err(io::Error::new(io::ErrorKind::PermissionDenied, e)),
+            response => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected response: {:?}", response),
+            )),
+        }
+    }
+
+    /// 要求を送り、応答を1つ受け取る
+    pub async fn request(&mut self, request: &ControlRequest) -> io::Result<ControlResponse> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+
+        self.next_response().await
+    }
+
+    /// 次の応答を受け取る
+    pub async fn next_response(&mut self) -> io::Result<ControlResponse> {
+        match self.lines.next_line().await? {
+            Some(line) => Ok(serde_json::from_str(&line)?),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed by studio",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn write_token_is_user_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONTROL_TOKEN_FILE);
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_token(&path, "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,58 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+/// tauriのコマンドからフロントエンドへ返すエラー
+/// codeはフロントエンドで分岐に使うため、一度決めたら変更しない
+pub trait ErrorCode: Error {
+    fn code(&self) -> &'static str;
+
+    fn details(&self) -> Option<String> {
+        self.source().map(|source| source.to_string())
+    }
+}
+
+/// エラーをフロントエンドやプラグインへ渡すときの形
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorPayload {
+    pub code: String,
+    pub message: String,
+    pub details: Option<String>,
+}
+
+impl ErrorPayload {
+    pub fn new<E: ErrorCode>(error: &E) -> Self {
+        Self {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            details: error.details(),
+        }
+    }
+}
+
+/// { code, message, details } の形でシリアライズする
+pub fn serialize_error<E: ErrorCode, S: Serializer>(
+    error: &E,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    ErrorPayload::new(error).serialize(serializer)
+}
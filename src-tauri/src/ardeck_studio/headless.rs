@@ -0,0 +1,119 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{
+    ardeck::core::{ArdeckCore, ArdeckEvent},
+    control,
+    error::ErrorCode,
+    plugin,
+    settings::{self, definitions::ardeck::ArdeckProfileConfigJSON, SettingsStore},
+    shutdown,
+};
+
+/// プロファイルにボーレートが設定されていない時に使うボーレート
+const DEFAULT_BAUD_RATE: u32 = 9600;
+
+/// tauriのウィンドウを使わずに、デバイスマネージャーとプラグインサーバーを起動する
+pub async fn run() {
+    log::info!("ardeck studio {} (headless)", env!("CARGO_PKG_VERSION"));
+
+    if let Err(e) = settings::core::init() {
+        log::error!("Failed to init settings dir: {}", e);
+        return;
+    }
+
+    plugin::core::server_init().await;
+    if let Err(e) = control::start().await {
+        // 操作する手段がなくなるため、起動をやめる
+        log::error!("Failed to start control server: {}", e);
+        shutdown::shutdown().await;
+        return;
+    }
+
+    // 最初のポート一覧を受け取れるように、監視を始める前に購読する
+    let mut events = ArdeckCore::subscribe();
+    ArdeckCore::serial_watch();
+
+    let signal = shutdown::wait_for_signal();
+    tokio::pin!(signal);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(ArdeckEvent::Ports(_)) => open_configured_devices().await,
+                Ok(ArdeckEvent::Open(port_name)) => log::info!("Device opened: {}", port_name),
+                Ok(ArdeckEvent::Close(port_name)) => log::info!("Device closed: {}", port_name),
+                Ok(ArdeckEvent::Message { .. }) => (),
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Ardeck event lagged: {} events skipped", n);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = &mut signal => break,
+        }
+    }
+
+    shutdown::shutdown().await;
+}
+
+/// プロファイルが保存されているデバイスのうち、未接続のものを開く
+async fn open_configured_devices() {
+    let profiles = match ArdeckProfileConfigJSON::new().load().await {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            log::error!("Failed to load ardeck profile config: {}", e);
+            return;
+        }
+    };
+
+    let connecting = ArdeckCore::get_connecting_serials().await;
+
+    let ports = match ArdeckCore::get_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            log::error!("Failed to get ports: {}", e);
+            return;
+        }
+    };
+
+    for (device_id, port) in ports {
+        if connecting.contains(&port.port_name) {
+            continue;
+        }
+
+        let profile = match profiles.iter().find(|p| p.device_id == device_id) {
+            Some(profile) => profile,
+            None => continue,
+        };
+
+        let baud_rate = profile.baud_rate.unwrap_or(DEFAULT_BAUD_RATE);
+
+        log::info!("Auto open: {} ({}) {}", port.port_name, device_id, baud_rate);
+
+        if let Err(e) = ArdeckCore::open(&port.port_name, baud_rate).await {
+            log::error!("{}", e);
+
+            if let Some(details) = e.details() {
+                log::error!("{}", details);
+            }
+        }
+    }
+}
@@ -16,46 +16,389 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+pub mod core;
+pub mod error;
+pub mod host;
+pub mod install;
 pub mod manager;
+pub mod manifest;
+pub mod output;
+pub mod parameter;
+pub mod permission;
+pub mod protocol;
+pub mod queue;
+pub mod runtime;
 pub mod server;
+pub mod subscription;
+pub mod supervisor;
 pub mod tauri;
+pub mod watcher;
 
+use chrono::Utc;
 use futures_util::SinkExt;
+use semver::Version;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use error::{PluginError, RequestError};
+use host::{HostRequest, HostResult};
+use output::PluginOutput;
+use parameter::ActionParameter;
 use server::PluginServerSink;
-use std::sync::Arc;
+use permission::PluginPermission;
+use queue::{OutboundQueue, QueueMetrics};
+use runtime::PluginRuntime;
+use subscription::{SubscribedEvent, Subscription};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+use supervisor::{CrashHistory, PluginStatus};
 use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
 
-use tokio::{net::TcpStream, sync::Mutex};
+use tokio::{net::TcpStream, sync::Mutex, time::Instant};
+use uuid::Uuid;
 
 use super::action::Action;
 
+use self::core::{PluginActionFailure, PluginEvent, PluginStatusEvent};
+use protocol::{ACTION_RESULT_SINCE, EVENTS_SINCE};
+
 pub static PLUGIN_DIR: &'static str = "./plugins";
 
+/// プラグインへ認証トークンを渡す環境変数名
+pub static PLUGIN_TOKEN_ENV: &str = "ARDECK_PLUGIN_TOKEN";
+
+/// ActionResultを待つ時間。過ぎたら失敗として記録する
+const ACTION_RESULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// プラグインごとに残す、失敗したアクションの数
+const ACTION_FAILURE_HISTORY: usize = 20;
+
+/// 結果を待っているアクション
+#[derive(Debug)]
+struct PendingAction {
+    target_action_id: String,
+    sent_at: Instant,
+}
+
 #[derive(Debug)]
 pub struct Plugin {
     pub manifest: PluginManifestJSON, //TODO: PluginManifest
+    /// actions.jsonのアクションと、セッション中に登録されたアクション
     pub actions: PluginActionJSON,
-    // pub process: Arc<Mutex<std::process::Child>>,
+    /// actions.jsonで宣言されたアクション
+    static_actions: PluginActionJSON,
+    /// プラグインのディレクトリ
+    pub dir: PathBuf,
+    /// 接続先のプラグインサーバーのポート番号
+    pub server_port: u16,
+    /// runtimeが指定されている場合の、インタプリタの実行ファイル
+    pub interpreter: Option<PathBuf>,
+    pub process: Option<Child>,
+    /// プロセスのstdout/stderrの記録
+    pub output: PluginOutput,
+    pub status: PluginStatus,
+    /// 無効なプラグインは起動せず、アクションも送らない
+    pub enabled: bool,
+    /// ユーザーが許可した権限
+    pub granted_permissions: Vec<PluginPermission>,
+    /// ユーザーに確認した権限。許可しなかったものも含む
+    pub reviewed_permissions: Vec<PluginPermission>,
+    pub crash_history: CrashHistory,
+    /// 起動ごとに生成される認証トークン
+    token: Option<String>,
+    /// セッションで使うプロトコルのバージョン
+    pub protocol_version: Option<Version>,
     pub session: Option<Arc<Mutex<TcpStream>>>,
     pub server_sink: Option<Arc<Mutex<PluginServerSink>>>,
+    /// セッションが始まるまで待たせているアクション
+    pub outbound: OutboundQueue,
+    /// action_idごとの、結果を待っているアクション
+    pending_actions: HashMap<String, PendingAction>,
+    /// 最近失敗したアクション。古いものから捨てる
+    action_failures: VecDeque<PluginActionFailure>,
+    /// セッション中に購読しているイベント
+    subscriptions: Vec<Subscription>,
 }
 
 impl Plugin {
     pub fn new(
         manifest: PluginManifestJSON,
         actions: PluginActionJSON,
-        // process: Arc<Mutex<std::process::Child>>,
+        dir: PathBuf,
+        server_port: u16,
         // session: Arc<Mutex<WebSocket>>
     ) -> Plugin {
+        let output = PluginOutput::new(&manifest.id);
+
         Plugin {
             manifest,
+            static_actions: actions.clone(),
             actions,
-            // process,
+            dir,
+            server_port,
+            interpreter: None,
+            process: None,
+            output,
+            status: PluginStatus::Stopped,
+            enabled: true,
+            granted_permissions: Vec::new(),
+            reviewed_permissions: Vec::new(),
+            crash_history: CrashHistory::default(),
+            token: None,
+            protocol_version: None,
             session: None,
             server_sink: None,
+            outbound: OutboundQueue::default(),
+            pending_actions: HashMap::new(),
+            action_failures: VecDeque::new(),
+            subscriptions: Vec::new(),
+        }
+    }
+
+    /// プラグインのプロセスを起動する
+    /// 起動に失敗した場合はクラッシュとして扱い、エラーを返す
+    pub fn start(&mut self) -> std::io::Result<()> {
+        // プラグインの実行ファイルのパスを取得
+        let plugin_main_path = self.dir.join(&self.manifest.main);
+
+        log::info!("Executing plugin: {}", &self.manifest.name);
+
+        // runtimeが指定されていれば、インタプリタにmainを渡して実行する
+        let mut command = match (self.manifest.runtime, &self.interpreter) {
+            (None, _) => Command::new(plugin_main_path),
+            (Some(_), Some(interpreter)) => {
+                let mut command = Command::new(interpreter);
+                command.arg(plugin_main_path);
+                command
+            }
+            (Some(runtime), None) => {
+                let e = std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Interpreter for {:?} is not resolved", runtime),
+                );
+                log::error!("Failed to execute plugin {}: {}", self.manifest.id, e);
+                return Err(e);
+            }
+        };
+
+        // 起動ごとにトークンを作り直し、前回のプロセスのトークンを無効にする
+        let token = Uuid::new_v4().simple().to_string();
+
+        match command
+            .arg(self.server_port.to_string())
+            .env(PLUGIN_TOKEN_ENV, &token)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(mut process) => {
+                self.output.capture(&mut process);
+                self.process = Some(process);
+                self.token = Some(token);
+                self.set_status(PluginStatus::Starting);
+
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Failed to execute plugin {}: {}", self.manifest.id, e);
+                self.crashed(Instant::now());
+
+                Err(e)
+            }
+        }
+    }
+
+    /// プロセスが予期せず終了した時の処理
+    /// 再起動はsupervisorが行う
+    pub fn crashed(&mut self, now: Instant) {
+        self.process = None;
+        self.token = None;
+        self.protocol_version = None;
+        self.session = None;
+        self.server_sink = None;
+        self.pending_actions.clear();
+        self.subscriptions.clear();
+        self.reset_actions();
+        self.set_status(PluginStatus::Crashed);
+
+        match self.crash_history.record(now) {
+            Some(next_restart) => log::info!(
+                "Plugin {} will be restarted in {:?}",
+                self.manifest.id,
+                next_restart - now
+            ),
+            None => log::error!(
+                "Plugin {} crashed too many times. Giving up restarting.",
+                self.manifest.id
+            ),
+        }
+    }
+
+    /// プロセスを直ちに終了させる
+    pub fn kill(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            if let Err(e) = process.kill() {
+                log::error!("Failed to kill plugin {}: {}", self.manifest.id, e);
+            }
+            let _ = process.wait();
+        }
+    }
+
+    /// セッションが閉じた時の処理
+    /// 別のセッションに置き換わっている場合は何もしない
+    pub fn session_closed(&mut self, server_sink: &Arc<Mutex<PluginServerSink>>) {
+        let is_current = self
+            .server_sink
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, server_sink));
+        if !is_current {
+            return;
+        }
+
+        self.server_sink = None;
+        self.protocol_version = None;
+        self.pending_actions.clear();
+        self.subscriptions.clear();
+        self.reset_actions();
+
+        // プロセスが生きていれば再接続を待つ。終了していればsupervisorが処理する
+        if self.status == PluginStatus::Connected {
+            self.set_status(PluginStatus::Starting);
+        }
+    }
+
+    /// Helloで送られたトークンが起動時に渡したものと一致するか
+    pub fn verify_token(&self, token: &str) -> bool {
+        self.token
+            .as_deref()
+            .is_some_and(|expected| super::token::verify(expected, token))
+    }
+
+    /// セッションで決まったプロトコルが、sinceのバージョン以降か
+    pub fn supports(&self, since: &Version) -> bool {
+        self.protocol_version
+            .as_ref()
+            .is_some_and(|version| version >= since)
+    }
+
+    pub fn info(&self) -> PluginInfo {
+        PluginInfo {
+            manifest: self.manifest.clone(),
+            status: self.status,
+            enabled: self.enabled,
+            granted_permissions: self.granted_permissions.clone(),
+            pending_permissions: self.pending_permissions(),
+            queue: self.outbound.metrics(),
+        }
+    }
+
+    /// マニフェストで宣言され、まだユーザーに確認していない権限
+    /// 残っている間はプラグインを起動しない
+    pub fn pending_permissions(&self) -> Vec<PluginPermission> {
+        self.manifest
+            .permissions
+            .iter()
+            .filter(|permission| {
+                !self.granted_permissions.contains(permission)
+                    && !self.reviewed_permissions.contains(permission)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// マニフェストで宣言され、かつユーザーが許可した権限か
+    pub fn has_permission(&self, permission: PluginPermission) -> bool {
+        self.manifest.permissions.contains(&permission)
+            && self.granted_permissions.contains(&permission)
+    }
+
+    /// セッション中に使うアクションを登録する
+    /// actions.jsonで宣言されたアクションは変更できない
+    pub fn register_actions(&mut self, actions: Vec<PluginAction>) -> Result<(), RequestError> {
+        let mut ids = HashSet::new();
+        for action in &actions {
+            if action.id.trim().is_empty() {
+                return Err(RequestError::InvalidAction("id is empty".to_string()));
+            }
+            if !ids.insert(action.id.as_str()) {
+                return Err(RequestError::InvalidAction(format!("duplicate id {}", action.id)));
+            }
+            if self.is_static_action(&action.id) {
+                return Err(RequestError::StaticAction(action.id.clone()));
+            }
+        }
+
+        for action in actions {
+            log::debug!("Plugin {} registered action: {}", self.manifest.id, action.id);
+
+            match self.actions.iter_mut().find(|a| a.id == action.id) {
+                Some(registered) => *registered = action,
+                None => self.actions.push(action),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// セッション中に登録したアクションを削除する
+    /// 登録されていないidは無視する
+    pub fn remove_actions(&mut self, action_ids: &[String]) -> Result<(), RequestError> {
+        if let Some(action_id) = action_ids.iter().find(|id| self.is_static_action(id)) {
+            return Err(RequestError::StaticAction(action_id.clone()));
+        }
+
+        self.actions.retain(|action| !action_ids.contains(&action.id));
+
+        Ok(())
+    }
+
+    fn is_static_action(&self, action_id: &str) -> bool {
+        self.static_actions.iter().any(|action| action.id == action_id)
+    }
+
+    /// セッション中に登録されたアクションを捨て、actions.jsonのアクションに戻す
+    fn reset_actions(&mut self) {
+        if self.actions.len() == self.static_actions.len() {
+            return;
         }
+
+        self.actions = self.static_actions.clone();
+        self::core::emit(PluginEvent::ListChanged);
+    }
+
+    /// マッピングで使えるかを付けたアクションの一覧
+    pub fn action_infos(&self) -> Vec<PluginActionInfo> {
+        self.actions
+            .iter()
+            .map(|action| PluginActionInfo {
+                action: action.clone(),
+                available: self.enabled,
+            })
+            .collect()
+    }
+
+    /// 状態を変更し、変化があればイベントを発行する
+    pub fn set_status(&mut self, status: PluginStatus) {
+        if self.status == status {
+            return;
+        }
+
+        log::debug!("Plugin status: {} {:?} -> {:?}", self.manifest.id, self.status, status);
+
+        self.status = status;
+        self::core::emit(PluginEvent::Status(PluginStatusEvent {
+            plugin_id: self.manifest.id.clone(),
+            status,
+        }));
+        self::core::publish_event(SubscribedEvent::PluginStatus {
+            plugin_id: self.manifest.id.clone(),
+            status,
+        });
     }
 
     pub fn set_session(&mut self, session: Arc<Mutex<TcpStream>>) {
@@ -67,18 +410,167 @@ impl Plugin {
     }
 
     /// アクションが発生したことをプラグインに通知する
-    pub async fn send_action(&mut self, action: Action) {
-        let data = PluginMessage::Action(action);
+    /// 結果を返せるプラグインには、action_idを付けて結果を待つ
+    /// 起動中や再起動待ちでセッションがない場合は、キューに入れてHelloの完了を待つ
+    pub async fn send_action(&mut self, mut action: Action) -> Result<(), PluginError> {
+        if self.server_sink.is_none() && self.is_reconnecting() {
+            log::debug!("Plugin {} is not connected yet. Queued the action.", self.manifest.id);
+            self.outbound.push(action, Instant::now());
+            return Ok(());
+        }
 
+        let action_id = if self.supports(&ACTION_RESULT_SINCE) {
+            let action_id = Uuid::new_v4().to_string();
+            action.action_id = Some(action_id.clone());
+            Some(action_id)
+        } else {
+            None
+        };
+        let target_action_id = action.target.action_id.clone();
+
+        self.send_message(&PluginMessage::Action(action)).await?;
+
+        if let Some(action_id) = action_id {
+            self.pending_actions.insert(
+                action_id,
+                PendingAction {
+                    target_action_id,
+                    sent_at: Instant::now(),
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// イベントを購読する。購読済みの条件は追加しない
+    pub fn subscribe(&mut self, subscriptions: Vec<Subscription>) {
+        for subscription in subscriptions {
+            if !self.subscriptions.contains(&subscription) {
+                self.subscriptions.push(subscription);
+            }
+        }
+    }
+
+    /// 購読をやめる。条件が一致するものだけを削除する
+    pub fn unsubscribe(&mut self, subscriptions: &[Subscription]) {
+        self.subscriptions
+            .retain(|subscription| !subscriptions.contains(subscription));
+    }
+
+    /// 購読している条件に一致するイベントを受け取れるなら、送り先のセッションを返す
+    pub fn event_sink(&self, event: &SubscribedEvent) -> Option<Arc<Mutex<PluginServerSink>>> {
+        if !self.supports(&EVENTS_SINCE)
+            || !self.has_permission(PluginPermission::StateRead)
+            || !self.subscriptions.iter().any(|s| s.matches(event))
+        {
+            return None;
+        }
+
+        self.server_sink.clone()
+    }
+
+    /// キューに入れたアクションを送る。Helloが完了した後に呼ぶ
+    pub async fn flush_outbound(&mut self) {
+        let actions = self.outbound.drain(Instant::now());
+        if actions.is_empty() {
+            return;
+        }
+
+        log::info!("Sending {} queued actions to {}", actions.len(), self.manifest.id);
+
+        for action in actions {
+            if let Err(e) = self.send_action(action).await {
+                log::error!("Failed to send queued action: {}", e);
+            }
+        }
+    }
+
+    /// 間もなくセッションが始まる見込みがあるか
+    fn is_reconnecting(&self) -> bool {
+        match self.status {
+            PluginStatus::Starting => true,
+            PluginStatus::Crashed => self.crash_history.will_restart(),
+            PluginStatus::Connected | PluginStatus::Unresponsive | PluginStatus::Stopped => false,
+        }
+    }
+
+    /// プラグインから届いたアクションの結果を処理する
+    pub fn action_result(&mut self, action_id: &str, success: bool, message: Option<String>) {
+        let pending = match self.pending_actions.remove(action_id) {
+            Some(pending) => pending,
+            None => {
+                log::debug!(
+                    "Result for unknown or expired action from {}: {}",
+                    self.manifest.id,
+                    action_id
+                );
+                return;
+            }
+        };
+
+        if !success {
+            let message = message.unwrap_or_else(|| "Action failed".to_string());
+            self.record_action_failure(pending.target_action_id, message);
+        }
+    }
+
+    /// 結果が返ってこないまま時間が過ぎたアクションを失敗として記録する
+    pub fn expire_actions(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .pending_actions
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.sent_at) > ACTION_RESULT_TIMEOUT)
+            .map(|(action_id, _)| action_id.clone())
+            .collect();
+
+        for action_id in expired {
+            if let Some(pending) = self.pending_actions.remove(&action_id) {
+                self.record_action_failure(
+                    pending.target_action_id,
+                    format!("No result within {:?}", ACTION_RESULT_TIMEOUT),
+                );
+            }
+        }
+    }
+
+    fn record_action_failure(&mut self, action_id: String, message: String) {
+        log::warn!("Action failed: {}/{}: {}", self.manifest.id, action_id, message);
+
+        let failure = PluginActionFailure {
+            plugin_id: self.manifest.id.clone(),
+            action_id,
+            message,
+            timestamp: Utc::now().timestamp_millis(),
+        };
+
+        if self.action_failures.len() >= ACTION_FAILURE_HISTORY {
+            self.action_failures.pop_front();
+        }
+        self.action_failures.push_back(failure.clone());
+
+        self::core::emit(PluginEvent::ActionFailed(failure));
+    }
+
+    /// 最近失敗したアクションの一覧。新しいものが後ろ
+    pub fn action_failures(&self) -> Vec<PluginActionFailure> {
+        self.action_failures.iter().cloned().collect()
+    }
+
+    /// プラグインにメッセージを送る
+    pub async fn send_message(&mut self, data: &PluginMessage) -> Result<(), PluginError> {
         if let Some(server_sink) = self.server_sink.as_mut() {
             server_sink
                 .lock()
                 .await
-                .send(Message::Text(Utf8Bytes::from(
-                    &serde_json::to_string(&data).unwrap(),
-                )))
+                .send(Message::Text(Utf8Bytes::from(&serde_json::to_string(
+                    data,
+                )?)))
                 .await
-                .unwrap();
+                .map_err(|source| PluginError::Send {
+                    plugin_id: self.manifest.id.clone(),
+                    source,
+                })
         } else {
             log::error!(
                 "Plugin session has not started yet.\n\tPlugin name: {}\n\tPlugin id: {}",
@@ -86,6 +578,100 @@ impl Plugin {
             );
 
             // TODO: 対象のプラグインを再起動、もしくは停止、もしくはデバイスの停止
+            Err(PluginError::SessionNotStarted(self.manifest.id.clone()))
+        }
+    }
+
+    /// 止めるために、プロセスとセッションを取り出す
+    /// 状態はStoppedになり、終了を待つのは返したPluginStopperで行う
+    pub fn take_stopper(&mut self) -> PluginStopper {
+        // 終了中にsupervisorが再起動しないようにする
+        self.set_status(PluginStatus::Stopped);
+        self.outbound.clear();
+
+        // セッションを取り出すとsession_closedで片付けられないため、ここで片付ける
+        self.token = None;
+        self.protocol_version = None;
+        self.session = None;
+        self.pending_actions.clear();
+        self.subscriptions.clear();
+        self.reset_actions();
+
+        PluginStopper {
+            plugin_id: self.manifest.id.clone(),
+            process: self.process.take(),
+            server_sink: self.server_sink.take(),
+        }
+    }
+}
+
+/// 止めているプラグインのプロセスとセッション
+/// マネージャーやサーバーのロックを持たずに終了を待つために使う
+pub struct PluginStopper {
+    plugin_id: String,
+    process: Option<Child>,
+    server_sink: Option<Arc<Mutex<PluginServerSink>>>,
+}
+
+impl PluginStopper {
+    /// 終了を要求し、timeoutまでに終了しなければkillする
+    pub async fn stop(mut self, timeout: Duration) {
+        self.request_shutdown().await;
+        self.wait_or_kill(Instant::now() + timeout).await;
+    }
+
+    /// プラグインに終了を要求する
+    /// セッションが始まっていない場合は何もしない
+    pub async fn request_shutdown(&mut self) {
+        let server_sink = match self.server_sink.take() {
+            Some(server_sink) => server_sink,
+            None => return,
+        };
+        let mut server_sink = server_sink.lock().await;
+
+        match serde_json::to_string(&PluginMessage::Shutdown) {
+            Ok(message) => {
+                let message = Message::Text(Utf8Bytes::from(&message));
+                if let Err(e) = server_sink.send(message).await {
+                    log::warn!("Failed to send shutdown to plugin {}: {}", self.plugin_id, e);
+                }
+            }
+            Err(e) => log::error!("Failed to serialize shutdown: {}", e),
+        }
+
+        let _ = server_sink.close().await;
+    }
+
+    /// プロセスが終了するまでdeadlineまで待ち、終了しなければkillする
+    pub async fn wait_or_kill(mut self, deadline: Instant) {
+        let mut process = match self.process.take() {
+            Some(process) => process,
+            None => return,
+        };
+
+        loop {
+            match process.try_wait() {
+                Ok(Some(status)) => {
+                    log::info!("Plugin exited: {} ({})", self.plugin_id, status);
+                    return;
+                }
+                Ok(None) if Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Ok(None) => {
+                    log::warn!("Plugin did not exit in time. Killing: {}", self.plugin_id);
+
+                    if let Err(e) = process.kill() {
+                        log::error!("Failed to kill plugin {}: {}", self.plugin_id, e);
+                    }
+                    let _ = process.wait();
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Failed to wait plugin {}: {}", self.plugin_id, e);
+                    return;
+                }
+            }
         }
     }
 }
@@ -99,6 +685,36 @@ pub struct PluginManifestJSON {
     pub description: Option<String>,
     pub author: String,
     pub main: String,
+    /// mainを実行するインタプリタ。省略した場合はmainを直接実行する
+    #[serde(default)]
+    pub runtime: Option<PluginRuntime>,
+    /// プラグインが使う権限
+    #[serde(default)]
+    pub permissions: Vec<PluginPermission>,
+}
+
+/// フロントエンドへ返す、マニフェストとプラグインの状態
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginInfo {
+    #[serde(flatten)]
+    pub manifest: PluginManifestJSON,
+    pub status: PluginStatus,
+    pub enabled: bool,
+    pub granted_permissions: Vec<PluginPermission>,
+    /// ユーザーの確認を待っている権限
+    pub pending_permissions: Vec<PluginPermission>,
+    /// セッション開始前のアクションのキューの統計
+    pub queue: QueueMetrics,
+}
+
+/// フロントエンドへ返すアクションと、マッピングで使えるか
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginActionInfo {
+    #[serde(flatten)]
+    pub action: PluginAction,
+    pub available: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -107,6 +723,9 @@ pub struct PluginAction {
     name: String,
     id: String,
     description: Option<String>,
+    /// マッピングごとに設定するパラメーター
+    #[serde(default)]
+    parameters: Vec<ActionParameter>,
 }
 
 type PluginActionJSON = Vec<PluginAction>;
@@ -127,6 +746,10 @@ pub enum PluginMessage {
         plugin_version: String,
         ardeck_plugin_web_socket_version: String,
         plugin_id: String,
+        /// 起動時に環境変数で渡されたトークン
+        /// 0.1.0より前のプラグインは送らない
+        #[serde(default)]
+        token: String,
     },
     #[serde(rename = "1")]
     Success {
@@ -142,6 +765,50 @@ pub enum PluginMessage {
     },
     #[serde(rename = "3")]
     Action(Action),
+    #[serde(rename = "4")]
+    Shutdown, // OP4: Shutdown
+    #[serde(rename = "5")]
+    Request {
+        // OP5: Request (プラグイン -> ホスト)
+        message_id: String,
+        #[serde(flatten)]
+        request: HostRequest,
+    },
+    #[serde(rename = "6")]
+    Response {
+        // OP6: Response (ホスト -> プラグイン)
+        message_id: String,
+        #[serde(flatten)]
+        result: HostResult,
+    },
+    #[serde(rename = "7")]
+    ConfigChanged {
+        // OP7: ConfigChanged (ホスト -> プラグイン)
+        config: Map<String, Value>,
+    },
+    #[serde(rename = "8")]
+    ActionResult {
+        // OP8: ActionResult (プラグイン -> ホスト)
+        action_id: String,
+        success: bool,
+        /// 失敗した理由。ユーザーに表示される
+        #[serde(default)]
+        message: Option<String>,
+    },
+    #[serde(rename = "9")]
+    Subscribe {
+        // OP9: Subscribe (プラグイン -> ホスト)
+        message_id: String,
+        subscriptions: Vec<Subscription>,
+    },
+    #[serde(rename = "10")]
+    Unsubscribe {
+        // OP10: Unsubscribe (プラグイン -> ホスト)
+        message_id: String,
+        subscriptions: Vec<Subscription>,
+    },
+    #[serde(rename = "11")]
+    Event(SubscribedEvent), // OP11: Event (ホスト -> プラグイン)
 }
 
 #[derive(Serialize_repr, Deserialize_repr, Debug, Clone)]
@@ -152,6 +819,40 @@ pub enum PluginOpCode {
     Success,
     Message,
     Action,
+    Shutdown,
+    Request,
+    Response,
+    ConfigChanged,
+    ActionResult,
+    Subscribe,
+    Unsubscribe,
+    Event,
 }
 
-// TODO: add host.rs
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin() -> Plugin {
+        let manifest = PluginManifestJSON {
+            name: "Test".to_string(),
+            version: "1.0.0".to_string(),
+            id: "test".to_string(),
+            description: None,
+            author: "test".to_string(),
+            main: "main".to_string(),
+            runtime: None,
+            permissions: Vec::new(),
+        };
+
+        Plugin::new(manifest, Vec::new(), PathBuf::from("test"), 0)
+    }
+
+    #[test]
+    fn verify_token_rejects_before_launch() {
+        let plugin = plugin();
+
+        assert!(!plugin.verify_token(""));
+        assert!(!plugin.verify_token("0123abcd"));
+    }
+}
@@ -0,0 +1,482 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures_util::SinkExt;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Mutex,
+};
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+use crate::{
+    ardeck_studio::{
+        settings::{self, error::SettingsError},
+        switch_info::SwitchInfo,
+    },
+    service::dir::Directories,
+};
+
+use super::{
+    error::{InstallError, ParameterError, PluginError, PluginLoadError, RequestError},
+    install::{self, StagedPlugin},
+    output::PluginOutputLine,
+    parameter::{self, ParameterValues},
+    permission::PluginPermission,
+    protocol,
+    server::PluginServer,
+    subscription::SubscribedEvent,
+    supervisor::PluginStatus,
+    PluginAction, PluginInfo, PluginMessage,
+};
+
+/// 入れ替えや削除のためにプラグインを止める時の待ち時間
+const PLUGIN_STOP_TIMEOUT: Duration = Duration::from_secs(3);
+/// 1つのプラグインへイベントを送るのを待つ時間
+const EVENT_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub static PLUGIN_SERVER: Lazy<Mutex<PluginServer>> =
+    Lazy::new(|| Mutex::new(PluginServer::new()));
+static PLUGIN_EVENT: Lazy<broadcast::Sender<PluginEvent>> =
+    Lazy::new(|| broadcast::channel(100).0);
+/// 購読しているプラグインへ送るイベント。1つのタスクが発生した順に送る
+static SUBSCRIBED_EVENT: Lazy<EventQueue> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    EventQueue {
+        sender,
+        receiver: Mutex::new(Some(receiver)),
+    }
+});
+
+/// イベントのキュー。受け取り側は送るタスクを起動する時に取り出す
+struct EventQueue {
+    sender: UnboundedSender<SubscribedEvent>,
+    receiver: Mutex<Option<UnboundedReceiver<SubscribedEvent>>>,
+}
+
+/// プラグインサーバーから通知されるイベント
+#[derive(Clone, Debug)]
+pub enum PluginEvent {
+    /// プラグインの状態が変化した
+    Status(PluginStatusEvent),
+    /// プラグインからユーザーへの通知
+    Notification(PluginNotification),
+    /// プラグインの設定が変更された
+    ConfigChanged(PluginConfigEvent),
+    /// プラグインの一覧や、プラグインのアクションが変化した
+    ListChanged,
+    /// プラグインがアクションを処理できなかった
+    ActionFailed(PluginActionFailure),
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginStatusEvent {
+    pub plugin_id: String,
+    pub status: PluginStatus,
+}
+
+/// プラグインが処理できなかったアクション
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginActionFailure {
+    pub plugin_id: String,
+    /// プラグインのアクションのID
+    pub action_id: String,
+    pub message: String,
+    /// 失敗を記録した時刻(UNIXミリ秒)
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginNotification {
+    pub plugin_id: String,
+    pub title: String,
+    pub body: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginConfigEvent {
+    pub plugin_id: String,
+    pub config: Map<String, Value>,
+}
+
+/// プラグインサーバーのイベントを購読する
+pub fn subscribe() -> broadcast::Receiver<PluginEvent> {
+    PLUGIN_EVENT.subscribe()
+}
+
+pub(super) fn emit(event: PluginEvent) {
+    // 購読者がいない場合はErrになるが、問題はない
+    let _ = PLUGIN_EVENT.send(event);
+}
+
+/// プラグインサーバーを起動し、プラグインディレクトリ内のプラグインを全て実行する
+pub async fn server_init() {
+    log::info!("Initializing plugin server...");
+
+    start_event_dispatcher().await;
+
+    let mut server = PLUGIN_SERVER.lock().await;
+    let plugin_dir = match Directories::get_plugin_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            log::error!("[init]  Failed to get plugin dir: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = Directories::init(plugin_dir) {
+        log::error!("[init] Failed to init plugin dir: {}", e);
+        return;
+    };
+
+    match server.start().await {
+        Ok(addr) => {
+            log::info!("Plugin server started: {}", addr);
+            server.execute_plugin_all().await;
+        }
+        Err(e) => log::error!("Failed to start plugin server: {}", e),
+    };
+}
+
+/// 全てのプラグインを終了する
+pub async fn stop_plugin_all(timeout: Duration) {
+    PLUGIN_SERVER.lock().await.stop_plugin_all(timeout).await;
+}
+
+/// プラグインの有効、無効を切り替える
+pub async fn set_plugin_enabled(plugin_id: &str, enabled: bool) -> Result<PluginInfo, PluginError> {
+    let (info, stopper) = PLUGIN_SERVER
+        .lock()
+        .await
+        .set_enabled(plugin_id, enabled)
+        .await?;
+
+    // 終了を待つ間、サーバーのロックを持たない
+    if let Some(stopper) = stopper {
+        stopper.stop(PLUGIN_STOP_TIMEOUT).await;
+    }
+
+    emit(PluginEvent::ListChanged);
+    Ok(info)
+}
+
+/// プラグインのディレクトリの変更を受けて、プラグインを読み込み直す
+pub async fn reload_plugin_dir(dir: &Path, changed: &[PathBuf]) {
+    let mut reload = match PLUGIN_SERVER.lock().await.begin_reload(dir, changed).await {
+        Some(reload) => reload,
+        None => return,
+    };
+
+    // 終了を待つ間、サーバーのロックを持たない
+    if let Some(stopper) = reload.stopper.take() {
+        stopper.stop(PLUGIN_STOP_TIMEOUT).await;
+    }
+
+    PLUGIN_SERVER.lock().await.finish_reload(reload).await;
+
+    emit(PluginEvent::ListChanged);
+}
+
+/// ユーザーが許可したプラグインの権限を保存する
+pub async fn set_plugin_permissions(
+    plugin_id: &str,
+    permissions: Vec<PluginPermission>,
+) -> Result<PluginInfo, PluginError> {
+    let info = PLUGIN_SERVER
+        .lock()
+        .await
+        .set_permissions(plugin_id, permissions)
+        .await?;
+
+    emit(PluginEvent::ListChanged);
+    Ok(info)
+}
+
+/// プラグインが最近処理できなかったアクションの一覧を取得する
+pub async fn get_action_failures(plugin_id: &str) -> Result<Vec<PluginActionFailure>, PluginError> {
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+    let plugin_manager = plugin_manager.lock().await;
+
+    plugin_manager
+        .get(plugin_id)
+        .map(|plugin| plugin.action_failures())
+        .ok_or_else(|| PluginError::NotFound(plugin_id.to_string()))
+}
+
+/// セッション中のプラグインがアクションを登録する
+pub async fn register_plugin_actions(
+    plugin_id: &str,
+    actions: Vec<PluginAction>,
+) -> Result<(), RequestError> {
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+    plugin_manager
+        .lock()
+        .await
+        .get_mut(plugin_id)
+        .ok_or_else(|| PluginError::NotFound(plugin_id.to_string()))?
+        .register_actions(actions)?;
+
+    emit(PluginEvent::ListChanged);
+    Ok(())
+}
+
+/// セッション中のプラグインが登録したアクションを削除する
+pub async fn remove_plugin_actions(
+    plugin_id: &str,
+    action_ids: &[String],
+) -> Result<(), RequestError> {
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+    plugin_manager
+        .lock()
+        .await
+        .get_mut(plugin_id)
+        .ok_or_else(|| PluginError::NotFound(plugin_id.to_string()))?
+        .remove_actions(action_ids)?;
+
+    emit(PluginEvent::ListChanged);
+    Ok(())
+}
+
+/// プラグインの最近の出力を取得する
+pub async fn get_plugin_output(plugin_id: &str) -> Result<Vec<PluginOutputLine>, PluginError> {
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+    let plugin_manager = plugin_manager.lock().await;
+
+    plugin_manager
+        .get(plugin_id)
+        .map(|plugin| plugin.output.recent())
+        .ok_or_else(|| PluginError::NotFound(plugin_id.to_string()))
+}
+
+/// 読み込めなかったプラグインの一覧を取得する
+pub async fn get_plugin_errors() -> Vec<PluginLoadError> {
+    PLUGIN_SERVER.lock().await.get_load_errors()
+}
+
+/// アーカイブからプラグインをインストールし、起動する
+/// 同じIDのプラグインがある場合は入れ替える
+/// 返り値のpending_permissionsが空でなければ、ユーザーが確認するまで起動しない
+pub async fn install_plugin(archive: PathBuf) -> Result<PluginInfo, InstallError> {
+    let plugin_dir = Directories::get_plugin_dir().map_err(|source| InstallError::Io {
+        path: PathBuf::from("plugins"),
+        source,
+    })?;
+
+    log::info!("Installing plugin: {}", archive.display());
+
+    // 展開と検証は時間がかかるため、別スレッドで行う
+    let staged =
+        tokio::task::spawn_blocking(move || install::stage(&archive, &plugin_dir)).await??;
+
+    let result = install_staged(&staged).await;
+    staged.cleanup();
+
+    if result.is_ok() {
+        emit(PluginEvent::ListChanged);
+    }
+    result
+}
+
+/// 同じIDのプラグインを止めてから、展開したプラグインを入れ替えて登録する
+async fn install_staged(staged: &StagedPlugin) -> Result<PluginInfo, InstallError> {
+    let mut pending = PLUGIN_SERVER.lock().await.begin_install(staged).await?;
+
+    // 終了を待つ間、サーバーのロックを持たない
+    if let Some(stopper) = pending.stopper.take() {
+        stopper.stop(PLUGIN_STOP_TIMEOUT).await;
+    }
+
+    PLUGIN_SERVER
+        .lock()
+        .await
+        .finish_install(staged, pending)
+        .await
+}
+
+/// プラグインを停止し、ファイルを削除する
+pub async fn uninstall_plugin(plugin_id: &str) -> Result<(), InstallError> {
+    let mut plugin = PLUGIN_SERVER.lock().await.unregister(plugin_id).await?;
+    emit(PluginEvent::ListChanged);
+
+    // 終了を待つ間、サーバーのロックを持たない
+    plugin.take_stopper().stop(PLUGIN_STOP_TIMEOUT).await;
+
+    std::fs::remove_dir_all(&plugin.dir).map_err(|source| InstallError::Io {
+        path: plugin.dir.clone(),
+        source,
+    })?;
+
+    // 同じIDで入れ直した時に引き継がないよう、プラグインの設定も消す
+    if let Err(e) = settings::core::set_granted_permissions(plugin_id, Vec::new(), Vec::new()).await
+    {
+        log::warn!("Failed to remove granted permissions: {}", e);
+    }
+    if let Err(e) = settings::core::set_plugin_enabled(plugin_id, true).await {
+        log::warn!("Failed to remove disabled state: {}", e);
+    }
+    if let Err(e) = settings::core::remove_plugin_config(plugin_id).await {
+        log::warn!("Failed to remove plugin config: {}", e);
+    }
+
+    log::info!("Uninstalled plugin: {}", plugin_id);
+
+    Ok(())
+}
+
+/// マッピングに設定されたパラメーターを、読み込まれているプラグインの宣言で検証する
+/// プラグインやアクションが読み込まれていない場合は検証できないため、そのまま返す
+pub async fn validate_action_parameters(
+    plugin_id: &str,
+    action_id: &str,
+    values: &ParameterValues,
+) -> Result<ParameterValues, ParameterError> {
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+    let plugin_manager = plugin_manager.lock().await;
+
+    let action = plugin_manager
+        .get(plugin_id)
+        .and_then(|plugin| plugin.actions.iter().find(|action| action.id == action_id));
+
+    match action {
+        Some(action) => parameter::validate(&action.parameters, values),
+        None => {
+            log::warn!(
+                "Cannot validate parameters. Action is not loaded: {}/{}",
+                plugin_id,
+                action_id
+            );
+            Ok(values.clone())
+        }
+    }
+}
+
+/// プラグインの設定を保存し、変更を通知する
+/// プラグイン自身による変更の場合は、そのプラグインには通知しない
+pub async fn set_plugin_config(
+    plugin_id: &str,
+    config: Map<String, Value>,
+    notify_plugin: bool,
+) -> Result<(), SettingsError> {
+    settings::core::set_plugin_config(plugin_id, config.clone()).await?;
+
+    emit(PluginEvent::ConfigChanged(PluginConfigEvent {
+        plugin_id: plugin_id.to_string(),
+        config: config.clone(),
+    }));
+
+    if !notify_plugin {
+        return Ok(());
+    }
+
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+    let mut plugin_manager = plugin_manager.lock().await;
+
+    if let Some(plugin) = plugin_manager.get_mut(plugin_id) {
+        if plugin.server_sink.is_some() && plugin.supports(&protocol::CONFIG_CHANGED_SINCE) {
+            if let Err(e) = plugin
+                .send_message(&PluginMessage::ConfigChanged { config })
+                .await
+            {
+                log::warn!("Failed to notify config change: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn send_action_to_plugins(device_id: String, data: SwitchInfo) {
+    PLUGIN_SERVER.lock().await.put_action(&device_id, data.clone()).await;
+
+    publish_event(SubscribedEvent::Switch {
+        device_id,
+        switch_info: data,
+    });
+}
+
+/// イベントを購読しているプラグインへ送る
+/// 呼び出し元がプラグインのロックを持っている場合があるため、キューに積んで別のタスクで送る
+pub fn publish_event(event: SubscribedEvent) {
+    let _ = SUBSCRIBED_EVENT.sender.send(event);
+}
+
+/// キューに積まれたイベントを、発生した順にプラグインへ送るタスクを起動する
+/// 2回目以降の呼び出しでは何もしない
+async fn start_event_dispatcher() {
+    let mut receiver = match SUBSCRIBED_EVENT.receiver.lock().await.take() {
+        Some(receiver) => receiver,
+        None => return,
+    };
+
+    tokio::spawn(async move {
+        let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+
+        while let Some(event) = receiver.recv().await {
+            // 送る間マネージャーのロックを持たないよう、送り先だけ集める
+            let sinks: Vec<_> = plugin_manager
+                .lock()
+                .await
+                .values()
+                .filter_map(|plugin| {
+                    plugin
+                        .event_sink(&event)
+                        .map(|sink| (plugin.manifest.id.clone(), sink))
+                })
+                .collect();
+            if sinks.is_empty() {
+                continue;
+            }
+
+            let message = match serde_json::to_string(&PluginMessage::Event(event)) {
+                Ok(message) => message,
+                Err(e) => {
+                    log::error!("Failed to serialize event: {}", e);
+                    continue;
+                }
+            };
+
+            // 応答しないプラグインで後のイベントが止まらないよう、待つ時間を区切る
+            for (plugin_id, sink) in sinks {
+                let send = async {
+                    sink.lock()
+                        .await
+                        .send(Message::Text(Utf8Bytes::from(&message)))
+                        .await
+                };
+                match tokio::time::timeout(EVENT_SEND_TIMEOUT, send).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::error!("Failed to send event to {}: {}", plugin_id, e),
+                    Err(_) => log::warn!("Sending event to {} timed out", plugin_id),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,397 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    error::Error as _,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+use tokio_tungstenite::tungstenite;
+
+use super::{
+    parameter::ParameterType, permission::PluginPermission, runtime::PluginRuntime,
+};
+
+use crate::ardeck_studio::{
+    ardeck::error::DeviceError,
+    error::{serialize_error, ErrorCode},
+    settings::error::SettingsError,
+};
+
+/// プラグインサーバーのエラー
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error("Plugin not found: {0}")]
+    NotFound(String),
+    #[error("Plugin session has not started yet: {0}")]
+    SessionNotStarted(String),
+    #[error("Failed to send message to plugin: {plugin_id}")]
+    Send {
+        plugin_id: String,
+        #[source]
+        source: tungstenite::Error,
+    },
+    #[error("Failed to serialize plugin message")]
+    Serde(#[from] serde_json::Error),
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+}
+
+/// マニフェストとアクションの読み込み、検証のエラー
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Failed to read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to parse {} at line {}, column {}", path.display(), source.line(), source.column())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Missing required field: {0}")]
+    MissingField(&'static str),
+    #[error("Invalid plugin id: {0}")]
+    InvalidId(String),
+    #[error("Invalid plugin version: {version}")]
+    InvalidVersion {
+        version: String,
+        #[source]
+        source: semver::Error,
+    },
+    #[error("main must be a relative path inside the plugin: {0}")]
+    InvalidMain(String),
+    #[error("main not found: {}", .0.display())]
+    MainNotFound(PathBuf),
+    #[error("main is not executable: {}", .0.display())]
+    MainNotExecutable(PathBuf),
+    #[error("Duplicate action id: {0}")]
+    DuplicateAction(String),
+}
+
+impl ErrorCode for ManifestError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Io { .. } => "plugin.manifest.io",
+            Self::Parse { .. } => "plugin.manifest.parse",
+            Self::MissingField(_) => "plugin.manifest.missing_field",
+            Self::InvalidId(_) => "plugin.manifest.invalid_id",
+            Self::InvalidVersion { .. } => "plugin.manifest.invalid_version",
+            Self::InvalidMain(_) => "plugin.manifest.invalid_main",
+            Self::MainNotFound(_) => "plugin.manifest.main_not_found",
+            Self::MainNotExecutable(_) => "plugin.manifest.main_not_executable",
+            Self::DuplicateAction(_) => "plugin.manifest.duplicate_action",
+        }
+    }
+}
+
+impl ManifestError {
+    /// 問題のあるファイル
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Self::Io { path, .. } | Self::Parse { path, .. } => Some(path),
+            Self::MainNotFound(path) | Self::MainNotExecutable(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// JSONの構文エラーの位置 (行, 列)
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Parse { source, .. } => Some((source.line(), source.column())),
+            _ => None,
+        }
+    }
+}
+
+/// プラグインを読み込めなかった理由
+/// 1つのプラグインが壊れていても他のプラグインは読み込み、これを一覧で返す
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginLoadError {
+    /// プラグインのディレクトリ
+    pub dir: PathBuf,
+    /// マニフェストから読み取れた場合のプラグインID
+    pub plugin_id: Option<String>,
+    pub code: String,
+    pub message: String,
+    pub details: Option<String>,
+    /// 問題のあるファイル
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl PluginLoadError {
+    pub fn new<E: ErrorCode>(dir: &Path, plugin_id: Option<String>, error: &E) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            plugin_id,
+            code: error.code().to_string(),
+            message: error.to_string(),
+            details: error.details(),
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn from_manifest_error(dir: &Path, plugin_id: Option<String>, error: &ManifestError) -> Self {
+        let position = error.position();
+
+        Self {
+            file: error.file().map(Path::to_path_buf),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            ..Self::new(dir, plugin_id, error)
+        }
+    }
+}
+
+/// プラグインの起動時のエラー
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Failed to read plugin directory")]
+    ReadDir(#[source] io::Error),
+    #[error("Plugin id {plugin_id} is already used by {}", other.display())]
+    DuplicateId { plugin_id: String, other: PathBuf },
+    #[error("Runtime {runtime:?} is not installed: {interpreter}. Install it or set its path in the settings.")]
+    RuntimeNotFound {
+        runtime: PluginRuntime,
+        interpreter: String,
+    },
+    #[error("Failed to execute plugin: {}", main.display())]
+    Spawn {
+        main: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl ErrorCode for LoadError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadDir(_) => "plugin.load.read_dir",
+            Self::DuplicateId { .. } => "plugin.load.duplicate_id",
+            Self::RuntimeNotFound { .. } => "plugin.load.runtime_not_found",
+            Self::Spawn { .. } => "plugin.load.spawn_failed",
+        }
+    }
+}
+
+/// プラグインのインストール、アンインストールのエラー
+#[derive(Debug, Error)]
+pub enum InstallError {
+    #[error("Unsupported archive: {}", .0.display())]
+    UnsupportedArchive(PathBuf),
+    #[error("Failed to access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to read zip archive")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Archive entry escapes the plugin directory: {0}")]
+    UnsafePath(String),
+    #[error("Archive entry is a link: {0}")]
+    LinkEntry(String),
+    #[error("Invalid plugin: {0}")]
+    Manifest(#[from] ManifestError),
+    #[error("Plugin is not installed: {0}")]
+    NotInstalled(String),
+    #[error("A directory that is not this plugin already exists: {}", .0.display())]
+    AlreadyExists(PathBuf),
+    #[error("Plugin server has not started yet")]
+    ServerNotStarted,
+    #[error("Install task failed")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl ErrorCode for InstallError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::UnsupportedArchive(_) => "plugin.install.unsupported_archive",
+            Self::Io { .. } => "plugin.install.io",
+            Self::Zip(_) => "plugin.install.zip",
+            Self::UnsafePath(_) => "plugin.install.unsafe_path",
+            Self::LinkEntry(_) => "plugin.install.link_entry",
+            Self::Manifest(e) => e.code(),
+            Self::NotInstalled(_) => "plugin.install.not_installed",
+            Self::AlreadyExists(_) => "plugin.install.already_exists",
+            Self::ServerNotStarted => "plugin.install.server_not_started",
+            Self::Task(_) => "plugin.install.task",
+        }
+    }
+
+    fn details(&self) -> Option<String> {
+        match self {
+            Self::Manifest(e) => e.details(),
+            _ => self.source().map(|source| source.to_string()),
+        }
+    }
+}
+
+impl Serialize for InstallError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
+
+/// アクションのパラメーターの検証エラー
+#[derive(Debug, Error)]
+pub enum ParameterError {
+    #[error("Unknown parameter: {0}")]
+    Unknown(String),
+    #[error("Missing required parameter: {0}")]
+    Missing(String),
+    #[error("Parameter {name} must be {expected:?}")]
+    TypeMismatch {
+        name: String,
+        expected: ParameterType,
+    },
+    #[error("Parameter {0} is not one of the allowed values")]
+    NotInEnum(String),
+    #[error("Parameter {0} is out of range")]
+    OutOfRange(String),
+}
+
+impl ErrorCode for ParameterError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Unknown(_) => "plugin.parameter.unknown",
+            Self::Missing(_) => "plugin.parameter.missing",
+            Self::TypeMismatch { .. } => "plugin.parameter.type_mismatch",
+            Self::NotInEnum(_) => "plugin.parameter.not_in_enum",
+            Self::OutOfRange(_) => "plugin.parameter.out_of_range",
+        }
+    }
+}
+
+/// プラグインとのハンドシェイクのエラー
+/// 接続を閉じる際の理由としてcodeを送る
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    #[error("The first message was not Hello")]
+    NotHello,
+    #[error("Unknown plugin: {0}")]
+    UnknownPlugin(String),
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+    #[error("Invalid protocol version: {version}")]
+    InvalidVersion {
+        version: String,
+        #[source]
+        source: semver::Error,
+    },
+    #[error("Protocol version {version} is not supported (supported: {min} - {max})")]
+    IncompatibleVersion {
+        version: String,
+        min: semver::Version,
+        max: semver::Version,
+    },
+    #[error("Handshake timed out")]
+    Timeout,
+    #[error("Connection closed during handshake")]
+    Closed,
+    #[error(transparent)]
+    Plugin(#[from] PluginError),
+}
+
+/// プラグインからの要求を処理する際のエラー
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("Invalid request: {0}")]
+    Invalid(#[source] serde_json::Error),
+    #[error("Permission denied: {0:?}")]
+    PermissionDenied(PluginPermission),
+    #[error("Invalid action: {0}")]
+    InvalidAction(String),
+    #[error("Action is declared in actions.json and cannot be changed: {0}")]
+    StaticAction(String),
+    #[error(transparent)]
+    Plugin(#[from] PluginError),
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+}
+
+impl ErrorCode for RequestError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Invalid(_) => "plugin.request.invalid",
+            Self::PermissionDenied(_) => "plugin.request.permission_denied",
+            Self::InvalidAction(_) => "plugin.request.invalid_action",
+            Self::StaticAction(_) => "plugin.request.static_action",
+            Self::Plugin(e) => e.code(),
+            Self::Device(e) => e.code(),
+            Self::Settings(e) => e.code(),
+        }
+    }
+
+    fn details(&self) -> Option<String> {
+        match self {
+            Self::Invalid(e) => Some(e.to_string()),
+            Self::PermissionDenied(_) | Self::InvalidAction(_) | Self::StaticAction(_) => None,
+            Self::Plugin(e) => e.details(),
+            Self::Device(e) => e.details(),
+            Self::Settings(e) => e.details(),
+        }
+    }
+}
+
+impl ErrorCode for HandshakeError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NotHello => "plugin.handshake.not_hello",
+            Self::UnknownPlugin(_) => "plugin.handshake.unknown_plugin",
+            Self::InvalidToken(_) => "plugin.handshake.invalid_token",
+            Self::InvalidVersion { .. } => "plugin.handshake.invalid_version",
+            Self::IncompatibleVersion { .. } => "plugin.handshake.incompatible_version",
+            Self::Timeout => "plugin.handshake.timeout",
+            Self::Closed => "plugin.handshake.closed",
+            Self::Plugin(e) => e.code(),
+        }
+    }
+}
+
+impl ErrorCode for PluginError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "plugin.not_found",
+            Self::SessionNotStarted(_) => "plugin.session_not_started",
+            Self::Send { .. } => "plugin.send_failed",
+            Self::Serde(_) => "plugin.serde",
+            Self::Settings(e) => e.code(),
+        }
+    }
+}
+
+impl Serialize for PluginError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
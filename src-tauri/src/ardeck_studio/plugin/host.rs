@@ -0,0 +1,306 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::ardeck_studio::{
+    ardeck::core::ArdeckCore,
+    error::ErrorPayload,
+    settings,
+    switch_info::{SwitchId, SwitchInfo, SwitchType},
+};
+
+use super::{
+    core::{self, PluginEvent, PluginNotification},
+    error::RequestError,
+    permission::PluginPermission,
+    PluginAction, PluginMessage,
+};
+
+/// プラグインからホストへの要求
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "method", content = "params")]
+pub enum HostRequest {
+    /// 接続中のデバイスの一覧を取得する
+    GetDevices,
+    /// デバイスのスイッチごとの最新の状態を取得する
+    GetSwitchStates { port_name: String },
+    /// デバイスへLEDなどのフィードバックを送る
+    /// フレームの形式はardeck::protocolを参照。仮想デバイスには送れない
+    SendFeedback {
+        port_name: String,
+        switch_id: SwitchId,
+        value: u16,
+    },
+    /// ホストのログに出力する
+    Log {
+        level: PluginLogLevel,
+        message: String,
+    },
+    /// ユーザーに通知する
+    Notify { title: String, body: Option<String> },
+    /// デバイスで使うマッピングプリセットを切り替える
+    SetMappingPreset {
+        device_id: String,
+        preset_id: String,
+    },
+    /// プラグインの設定を取得する
+    GetConfig,
+    /// プラグインの設定を更新する
+    /// valuesのキーだけを書き換え、値がnullのキーは削除する
+    SetConfig { values: Map<String, Value> },
+    /// セッション中だけ使えるアクションを登録する
+    /// 同じidのアクションが登録済みなら置き換える
+    RegisterActions { actions: Vec<PluginAction> },
+    /// 登録したアクションを削除する
+    RemoveActions { action_ids: Vec<String> },
+    /// 仮想の入力デバイスを作る
+    /// device_idはプラグインの中で一意なID
+    OpenVirtualDevice {
+        device_id: String,
+        name: Option<String>,
+    },
+    /// 仮想デバイスのスイッチの状態を送る
+    PutVirtualSwitch {
+        device_id: String,
+        switch_type: SwitchType,
+        switch_id: SwitchId,
+        switch_state: u16,
+    },
+    /// 仮想デバイスを閉じる
+    CloseVirtualDevice { device_id: String },
+}
+
+impl HostRequest {
+    /// この要求に必要な権限
+    pub fn required_permission(&self) -> Option<PluginPermission> {
+        match self {
+            Self::GetDevices | Self::GetSwitchStates { .. } => Some(PluginPermission::StateRead),
+            Self::SendFeedback { .. } => Some(PluginPermission::DeviceWrite),
+            Self::SetMappingPreset { .. } => Some(PluginPermission::PresetSwitch),
+            Self::OpenVirtualDevice { .. }
+            | Self::PutVirtualSwitch { .. }
+            | Self::CloseVirtualDevice { .. } => Some(PluginPermission::DeviceVirtual),
+            Self::Notify { .. } => Some(PluginPermission::NotificationShow),
+            // ログと自身の設定、アクションは権限なしで使える
+            Self::Log { .. }
+            | Self::GetConfig
+            | Self::SetConfig { .. }
+            | Self::RegisterActions { .. }
+            | Self::RemoveActions { .. } => None,
+        }
+    }
+}
+
+/// 要求の結果
+/// 成功時の値の形はmethodごとに決まっている
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "status", content = "result")]
+pub enum HostResult {
+    Ok(HostResponse),
+    Error(ErrorPayload),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HostResponse {
+    /// get_devices
+    Devices(Vec<PluginDeviceInfo>),
+    /// open_virtual_device
+    Device(PluginDeviceInfo),
+    /// get_switch_states
+    SwitchStates(Vec<SwitchInfo>),
+    /// get_config, set_config (更新後の設定)
+    Config(Map<String, Value>),
+    /// 値を返さない要求
+    Done,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginDeviceInfo {
+    pub port_name: String,
+    pub device_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginLogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<PluginLogLevel> for log::Level {
+    fn from(level: PluginLogLevel) -> Self {
+        match level {
+            PluginLogLevel::Error => log::Level::Error,
+            PluginLogLevel::Warn => log::Level::Warn,
+            PluginLogLevel::Info => log::Level::Info,
+            PluginLogLevel::Debug => log::Level::Debug,
+            PluginLogLevel::Trace => log::Level::Trace,
+        }
+    }
+}
+
+/// プラグインからの要求を処理し、返信するメッセージを返す
+pub async fn handle_request(
+    plugin_id: &str,
+    message_id: String,
+    request: HostRequest,
+) -> PluginMessage {
+    let result = match execute(plugin_id, request).await {
+        Ok(response) => HostResult::Ok(response),
+        Err(e) => {
+            log::warn!("Plugin request failed: {} ({})", plugin_id, e);
+            HostResult::Error(ErrorPayload::new(&e))
+        }
+    };
+
+    PluginMessage::Response { message_id, result }
+}
+
+/// 値を返さない要求が成功した時の返信
+pub fn done(message_id: String) -> PluginMessage {
+    PluginMessage::Response {
+        message_id,
+        result: HostResult::Ok(HostResponse::Done),
+    }
+}
+
+/// 権限のない要求への返信
+pub fn permission_denied(message_id: String, permission: PluginPermission) -> PluginMessage {
+    PluginMessage::Response {
+        message_id,
+        result: HostResult::Error(ErrorPayload::new(&RequestError::PermissionDenied(
+            permission,
+        ))),
+    }
+}
+
+/// 解釈できなかったメッセージに、message_idが読み取れれば返信する
+pub fn invalid_request(msg_str: &str, error: serde_json::Error) -> Option<PluginMessage> {
+    let value: serde_json::Value = serde_json::from_str(msg_str).ok()?;
+    let message_id = value.get("data")?.get("message_id")?.as_str()?.to_string();
+
+    Some(PluginMessage::Response {
+        message_id,
+        result: HostResult::Error(ErrorPayload::new(&RequestError::Invalid(error))),
+    })
+}
+
+async fn execute(plugin_id: &str, request: HostRequest) -> Result<HostResponse, RequestError> {
+    match request {
+        HostRequest::GetDevices => {
+            let devices = ArdeckCore::get_connecting_devices()
+                .await
+                .into_iter()
+                .map(|(port_name, device_id)| PluginDeviceInfo {
+                    port_name,
+                    device_id,
+                })
+                .collect();
+
+            Ok(HostResponse::Devices(devices))
+        }
+        HostRequest::GetSwitchStates { port_name } => Ok(HostResponse::SwitchStates(
+            ArdeckCore::get_switch_states(&port_name).await?,
+        )),
+        HostRequest::SendFeedback {
+            port_name,
+            switch_id,
+            value,
+        } => {
+            ArdeckCore::send_feedback(&port_name, switch_id, value).await?;
+            Ok(HostResponse::Done)
+        }
+        HostRequest::Log { level, message } => {
+            log::log!(level.into(), "[plugin:{}] {}", plugin_id, message);
+            Ok(HostResponse::Done)
+        }
+        HostRequest::Notify { title, body } => {
+            log::info!("[plugin:{}] Notification: {}", plugin_id, title);
+
+            core::emit(PluginEvent::Notification(PluginNotification {
+                plugin_id: plugin_id.to_string(),
+                title,
+                body,
+            }));
+            Ok(HostResponse::Done)
+        }
+        HostRequest::SetMappingPreset {
+            device_id,
+            preset_id,
+        } => {
+            settings::core::set_mapping_preset(&device_id, &preset_id).await?;
+            Ok(HostResponse::Done)
+        }
+        HostRequest::GetConfig => Ok(HostResponse::Config(
+            settings::core::get_plugin_config(plugin_id).await?,
+        )),
+        HostRequest::SetConfig { values } => {
+            let mut config = settings::core::get_plugin_config(plugin_id).await?;
+            for (key, value) in values {
+                if value.is_null() {
+                    config.remove(&key);
+                } else {
+                    config.insert(key, value);
+                }
+            }
+
+            core::set_plugin_config(plugin_id, config.clone(), false).await?;
+            Ok(HostResponse::Config(config))
+        }
+        HostRequest::RegisterActions { actions } => {
+            core::register_plugin_actions(plugin_id, actions).await?;
+            Ok(HostResponse::Done)
+        }
+        HostRequest::RemoveActions { action_ids } => {
+            core::remove_plugin_actions(plugin_id, &action_ids).await?;
+            Ok(HostResponse::Done)
+        }
+        HostRequest::OpenVirtualDevice { device_id, name } => {
+            let info = ArdeckCore::open_virtual(plugin_id, &device_id, name).await?;
+            Ok(HostResponse::Device(PluginDeviceInfo {
+                port_name: info.port_name,
+                device_id: info.device_id,
+            }))
+        }
+        HostRequest::PutVirtualSwitch {
+            device_id,
+            switch_type,
+            switch_id,
+            switch_state,
+        } => {
+            let mut switch_info = SwitchInfo::new();
+            switch_info.set_switch_type(switch_type);
+            switch_info.set_switch_id(switch_id);
+            switch_info.set_switch_state(switch_state);
+
+            ArdeckCore::put_virtual_switch(plugin_id, &device_id, switch_info).await?;
+            Ok(HostResponse::Done)
+        }
+        HostRequest::CloseVirtualDevice { device_id } => {
+            ArdeckCore::close_virtual(plugin_id, &device_id).await?;
+            Ok(HostResponse::Done)
+        }
+    }
+}
@@ -0,0 +1,412 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use tar::EntryType;
+use uuid::Uuid;
+
+use super::{
+    error::InstallError,
+    manifest::{self, MANIFEST_FILE},
+    PluginActionJSON, PluginManifestJSON,
+};
+
+/// 展開と検証が終わり、プラグインディレクトリへ移す前のプラグイン
+pub struct StagedPlugin {
+    /// 展開先の一時ディレクトリ
+    staging_dir: PathBuf,
+    /// マニフェストがあるディレクトリ
+    root: PathBuf,
+    pub manifest: PluginManifestJSON,
+    pub actions: PluginActionJSON,
+}
+
+impl StagedPlugin {
+    /// 一時ディレクトリを削除する
+    pub fn cleanup(&self) {
+        // 直下にマニフェストがあった場合は、一時ディレクトリごと移動済み
+        if !self.staging_dir.exists() {
+            return;
+        }
+
+        if let Err(e) = fs::remove_dir_all(&self.staging_dir) {
+            log::warn!("Failed to remove {}: {}", self.staging_dir.display(), e);
+        }
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> InstallError + '_ {
+    move |source| InstallError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// アーカイブをプラグインディレクトリ内の一時ディレクトリへ展開し、検証する
+/// 同じファイルシステム上に置くことで、後でrenameにより入れ替えられるようにする
+pub fn stage(archive: &Path, plugin_dir: &Path) -> Result<StagedPlugin, InstallError> {
+    let staging_dir = plugin_dir.join(format!(".staging-{}", Uuid::new_v4().simple()));
+    fs::create_dir_all(&staging_dir).map_err(io_error(&staging_dir))?;
+
+    let result = extract(archive, &staging_dir).and_then(|_| {
+        let root = find_root(&staging_dir)?;
+        let (manifest, actions) = manifest::load(&root)?;
+
+        Ok(StagedPlugin {
+            staging_dir: staging_dir.clone(),
+            root,
+            manifest,
+            actions,
+        })
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging_dir);
+    }
+
+    result
+}
+
+/// インストール先のディレクトリを決める
+/// 登録済みのプラグインはそのディレクトリを入れ替える。未登録の場合、同じ名前のディレクトリがあれば
+/// 別のプラグインやユーザーのファイルかもしれないため、上書きしない
+pub fn resolve_target(
+    plugin_dir: &Path,
+    plugin_id: &str,
+    registered_dir: Option<&Path>,
+) -> Result<PathBuf, InstallError> {
+    if let Some(dir) = registered_dir {
+        return Ok(dir.to_path_buf());
+    }
+
+    let target = plugin_dir.join(plugin_id);
+    if target.exists() {
+        return Err(InstallError::AlreadyExists(target));
+    }
+
+    Ok(target)
+}
+
+/// 展開したプラグインをtargetへ移す
+/// 既にtargetがある場合は退避してから入れ替え、失敗したら元に戻す
+pub fn swap_in(staged: &StagedPlugin, target: &Path) -> Result<(), InstallError> {
+    let backup = target.with_file_name(format!(".old-{}", Uuid::new_v4().simple()));
+    let has_old = target.exists();
+
+    if has_old {
+        fs::rename(target, &backup).map_err(io_error(target))?;
+    }
+
+    if let Err(source) = fs::rename(&staged.root, target) {
+        if has_old {
+            if let Err(e) = fs::rename(&backup, target) {
+                log::error!("Failed to restore {}: {}", target.display(), e);
+            }
+        }
+        return Err(io_error(target)(source));
+    }
+
+    if has_old {
+        if let Err(e) = fs::remove_dir_all(&backup) {
+            log::warn!("Failed to remove {}: {}", backup.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+fn extract(archive: &Path, dest: &Path) -> Result<(), InstallError> {
+    let name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if name.ends_with(".zip") {
+        extract_zip(archive, dest)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        extract_tar_gz(archive, dest)
+    } else {
+        Err(InstallError::UnsupportedArchive(archive.to_path_buf()))
+    }
+}
+
+fn extract_zip(archive: &Path, dest: &Path) -> Result<(), InstallError> {
+    let file = File::open(archive).map_err(io_error(archive))?;
+    let mut zip = zip::ZipArchive::new(file)?;
+
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+
+        let relative = match entry.enclosed_name() {
+            Some(relative) if manifest::is_relative_inside(&relative) => relative,
+            _ => return Err(InstallError::UnsafePath(entry.name().to_string())),
+        };
+        if entry.is_symlink() {
+            return Err(InstallError::LinkEntry(entry.name().to_string()));
+        }
+
+        let out = dest.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&out).map_err(io_error(&out))?;
+            continue;
+        }
+
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
+        }
+        let mut out_file = File::create(&out).map_err(io_error(&out))?;
+        io::copy(&mut entry, &mut out_file).map_err(io_error(&out))?;
+
+        // 実行権限を保つ
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&out, fs::Permissions::from_mode(mode & 0o777))
+                .map_err(io_error(&out))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_tar_gz(archive: &Path, dest: &Path) -> Result<(), InstallError> {
+    let file = File::open(archive).map_err(io_error(archive))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+
+    for entry in tar.entries().map_err(io_error(archive))? {
+        let mut entry = entry.map_err(io_error(archive))?;
+        let path = entry.path().map_err(io_error(archive))?.into_owned();
+        let name = path.display().to_string();
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Directory => (),
+            EntryType::Symlink | EntryType::Link => return Err(InstallError::LinkEntry(name)),
+            // pax拡張ヘッダーなどは展開しない
+            _ => continue,
+        }
+
+        if !manifest::is_relative_inside(&path) {
+            return Err(InstallError::UnsafePath(name));
+        }
+
+        let out = dest.join(&path);
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
+        }
+        entry.unpack(&out).map_err(io_error(&out))?;
+    }
+
+    Ok(())
+}
+
+/// マニフェストがあるディレクトリを探す
+/// アーカイブの直下か、直下にディレクトリが1つだけある場合はその中を見る
+fn find_root(staging_dir: &Path) -> Result<PathBuf, InstallError> {
+    if staging_dir.join(MANIFEST_FILE).is_file() {
+        return Ok(staging_dir.to_path_buf());
+    }
+
+    let entries = fs::read_dir(staging_dir)
+        .map_err(io_error(staging_dir))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+
+    match entries.as_slice() {
+        [dir] if dir.is_dir() && dir.join(MANIFEST_FILE).is_file() => Ok(dir.clone()),
+        // マニフェストの読み込みで見つからないエラーにする
+        _ => Ok(staging_dir.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn zip_options() -> SimpleFileOptions {
+        SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored)
+    }
+
+    fn write_tar_gz(path: &Path, entries: &[(&str, EntryType, &str)]) {
+        let file = File::create(path).unwrap();
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+        for (name, entry_type, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            // set_pathは".."を拒否するため、名前を直接書き込む
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            if *entry_type == EntryType::Symlink {
+                header.set_link_name(data).unwrap();
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            } else {
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append(&header, data.as_bytes()).unwrap();
+            }
+        }
+
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn resolve_target_refuses_unregistered_directory() {
+        let plugin_dir = tempdir().unwrap();
+        let existing = plugin_dir.path().join("com.example.plugin");
+        fs::create_dir(&existing).unwrap();
+        fs::write(existing.join("user-file.txt"), "keep me").unwrap();
+
+        let result = resolve_target(plugin_dir.path(), "com.example.plugin", None);
+
+        assert!(matches!(result, Err(InstallError::AlreadyExists(path)) if path == existing));
+        assert!(existing.join("user-file.txt").is_file());
+    }
+
+    #[test]
+    fn resolve_target_uses_registered_directory() {
+        let plugin_dir = tempdir().unwrap();
+        let registered = plugin_dir.path().join("somewhere-else");
+        fs::create_dir(&registered).unwrap();
+
+        let target =
+            resolve_target(plugin_dir.path(), "com.example.plugin", Some(&registered)).unwrap();
+
+        assert_eq!(target, registered);
+    }
+
+    #[test]
+    fn resolve_target_uses_new_directory() {
+        let plugin_dir = tempdir().unwrap();
+
+        let target = resolve_target(plugin_dir.path(), "com.example.plugin", None).unwrap();
+
+        assert_eq!(target, plugin_dir.path().join("com.example.plugin"));
+    }
+
+    #[test]
+    fn extract_zip_rejects_parent_path() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("../evil.txt", zip_options()).unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        assert!(matches!(extract(&archive, &dest), Err(InstallError::UnsafePath(_))));
+        assert!(!dir.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn extract_zip_rejects_symlink() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.add_symlink("link", "/etc/passwd", zip_options()).unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        assert!(matches!(extract(&archive, &dest), Err(InstallError::LinkEntry(_))));
+    }
+
+    #[test]
+    fn extract_zip_writes_files() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("plugin/main", zip_options()).unwrap();
+        zip.write_all(b"main").unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        extract(&archive, &dest).unwrap();
+
+        assert_eq!(fs::read_to_string(dest.join("plugin/main")).unwrap(), "main");
+    }
+
+    #[test]
+    fn extract_tar_gz_rejects_parent_path() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.tar.gz");
+        write_tar_gz(&archive, &[("../evil.txt", EntryType::Regular, "evil")]);
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        assert!(matches!(extract(&archive, &dest), Err(InstallError::UnsafePath(_))));
+        assert!(!dir.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn extract_tar_gz_rejects_absolute_path() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.tgz");
+        write_tar_gz(&archive, &[("/tmp/evil.txt", EntryType::Regular, "evil")]);
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        assert!(matches!(extract(&archive, &dest), Err(InstallError::UnsafePath(_))));
+    }
+
+    #[test]
+    fn extract_tar_gz_rejects_symlink() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.tar.gz");
+        write_tar_gz(&archive, &[("link", EntryType::Symlink, "/etc/passwd")]);
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        assert!(matches!(extract(&archive, &dest), Err(InstallError::LinkEntry(_))));
+    }
+
+    #[test]
+    fn extract_rejects_unknown_archive() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("plugin.rar");
+        fs::write(&archive, "").unwrap();
+
+        assert!(matches!(
+            extract(&archive, dir.path()),
+            Err(InstallError::UnsupportedArchive(_))
+        ));
+    }
+}
@@ -0,0 +1,189 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Component, Path},
+};
+
+use serde::de::DeserializeOwned;
+
+use super::{error::ManifestError, PluginActionJSON, PluginManifestJSON};
+
+pub static MANIFEST_FILE: &str = "manifest.json";
+pub static ACTIONS_FILE: &str = "actions.json";
+
+/// プラグインIDの最大長
+const MAX_ID_LEN: usize = 128;
+
+/// プラグインのディレクトリからマニフェストとアクションを読み込み、検証する
+pub fn load(dir: &Path) -> Result<(PluginManifestJSON, PluginActionJSON), ManifestError> {
+    let manifest: PluginManifestJSON = read_json(&dir.join(MANIFEST_FILE))?;
+    let actions: PluginActionJSON = read_json(&dir.join(ACTIONS_FILE))?;
+
+    validate_manifest(&manifest, dir)?;
+    validate_actions(&actions)?;
+
+    Ok((manifest, actions))
+}
+
+/// マニフェストが壊れていても、可能ならプラグインIDだけを読み取る
+pub fn read_id(dir: &Path) -> Option<String> {
+    let value: serde_json::Value = read_json(&dir.join(MANIFEST_FILE)).ok()?;
+
+    value.get("id")?.as_str().map(str::to_string)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, ManifestError> {
+    let file = File::open(path).map_err(|source| ManifestError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    serde_json::from_reader(std::io::BufReader::new(file)).map_err(|source| ManifestError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn validate_manifest(manifest: &PluginManifestJSON, dir: &Path) -> Result<(), ManifestError> {
+    let required = [
+        ("name", &manifest.name),
+        ("version", &manifest.version),
+        ("id", &manifest.id),
+        ("author", &manifest.author),
+        ("main", &manifest.main),
+    ];
+    for (field, value) in required {
+        if value.trim().is_empty() {
+            return Err(ManifestError::MissingField(field));
+        }
+    }
+
+    if !is_valid_id(&manifest.id) {
+        return Err(ManifestError::InvalidId(manifest.id.clone()));
+    }
+
+    semver::Version::parse(&manifest.version).map_err(|source| ManifestError::InvalidVersion {
+        version: manifest.version.clone(),
+        source,
+    })?;
+
+    // mainはプラグインのディレクトリ内を指していなければならない
+    if !is_relative_inside(Path::new(&manifest.main)) {
+        return Err(ManifestError::InvalidMain(manifest.main.clone()));
+    }
+
+    let main = dir.join(&manifest.main);
+    if !main.is_file() {
+        return Err(ManifestError::MainNotFound(main));
+    }
+    // インタプリタで実行する場合は、実行権限は不要
+    if manifest.runtime.is_none() && !is_executable(&main) {
+        return Err(ManifestError::MainNotExecutable(main));
+    }
+
+    Ok(())
+}
+
+fn validate_actions(actions: &PluginActionJSON) -> Result<(), ManifestError> {
+    let mut ids = HashSet::new();
+    for action in actions {
+        if action.id.trim().is_empty() {
+            return Err(ManifestError::MissingField("actions[].id"));
+        }
+        if !ids.insert(action.id.as_str()) {
+            return Err(ManifestError::DuplicateAction(action.id.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// 英数字と . - _ のみで、英数字で始まる
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() <= MAX_ID_LEN
+        && id.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// ..や絶対パスを含まない相対パスか
+pub fn is_relative_inside(path: &Path) -> bool {
+    let mut has_normal = false;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => has_normal = true,
+            Component::CurDir => (),
+            _ => return false,
+        }
+    }
+
+    has_normal
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_valid_id_accepts_plugin_ids() {
+        for id in ["com.example.plugin", "plugin_1", "a-b", "0abc"] {
+            assert!(is_valid_id(id), "{}", id);
+        }
+        assert!(is_valid_id(&"a".repeat(MAX_ID_LEN)));
+    }
+
+    #[test]
+    fn is_valid_id_rejects_unsafe_ids() {
+        for id in ["", ".hidden", "-a", "_a", "a/b", "a\\b", "..", "a b", "プラグイン"] {
+            assert!(!is_valid_id(id), "{}", id);
+        }
+        assert!(!is_valid_id(&"a".repeat(MAX_ID_LEN + 1)));
+    }
+
+    #[test]
+    fn is_relative_inside_accepts_inner_paths() {
+        for path in ["main.exe", "bin/main", "./bin/main"] {
+            assert!(is_relative_inside(Path::new(path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn is_relative_inside_rejects_outer_paths() {
+        for path in ["", ".", "../main", "bin/../../main", "/usr/bin/python"] {
+            assert!(!is_relative_inside(Path::new(path)), "{}", path);
+        }
+    }
+}
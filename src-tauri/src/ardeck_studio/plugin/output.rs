@@ -0,0 +1,240 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::Child,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::service::dir::Directories;
+
+/// メモリに残す、プラグインごとの最近の出力の行数
+const RECENT_LINES: usize = 500;
+/// ログファイルを切り替えるサイズ
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// 残す古いログファイルの数
+const MAX_ROTATED: usize = 3;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// プラグインが出力した1行
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginOutputLine {
+    pub stream: OutputStream,
+    pub line: String,
+    /// 受け取った時刻(UNIXミリ秒)
+    pub timestamp: i64,
+}
+
+/// プラグインのstdout/stderrの記録
+/// 再起動をまたいで、同じプラグインの出力を1つのログにまとめる
+#[derive(Debug, Clone)]
+pub struct PluginOutput {
+    plugin_id: String,
+    inner: Arc<Mutex<OutputInner>>,
+}
+
+#[derive(Debug, Default)]
+struct OutputInner {
+    recent: VecDeque<PluginOutputLine>,
+    /// 最初の出力で開く。開けなかった場合はメモリにだけ残す
+    file: Option<RotatingFile>,
+    file_failed: bool,
+}
+
+impl PluginOutput {
+    pub fn new(plugin_id: &str) -> Self {
+        Self {
+            plugin_id: plugin_id.to_string(),
+            inner: Arc::new(Mutex::new(OutputInner::default())),
+        }
+    }
+
+    /// 起動したプロセスのstdout/stderrを読み取り始める
+    /// 読み取りはプロセスが終了してパイプが閉じるまで続く
+    pub fn capture(&self, process: &mut Child) {
+        if let Some(stdout) = process.stdout.take() {
+            self.spawn_reader(stdout, OutputStream::Stdout);
+        }
+        if let Some(stderr) = process.stderr.take() {
+            self.spawn_reader(stderr, OutputStream::Stderr);
+        }
+    }
+
+    /// 最近の出力。古いものが先
+    pub fn recent(&self) -> Vec<PluginOutputLine> {
+        match self.inner.lock() {
+            Ok(inner) => inner.recent.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn spawn_reader<R: Read + Send + 'static>(&self, reader: R, stream: OutputStream) {
+        let output = self.clone();
+
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut buf = Vec::new();
+
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        // UTF-8でない出力も、読める部分は残す
+                        let line = String::from_utf8_lossy(&buf);
+                        output.push(stream, line.trim_end_matches(['\r', '\n']));
+                    }
+                    Err(e) => {
+                        log::debug!("Failed to read output of {}: {}", output.plugin_id, e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    fn push(&self, stream: OutputStream, line: &str) {
+        match stream {
+            OutputStream::Stdout => log::info!(target: &self.plugin_id, "{}", line),
+            OutputStream::Stderr => log::warn!(target: &self.plugin_id, "{}", line),
+        }
+
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        if inner.file.is_none() && !inner.file_failed {
+            match RotatingFile::open(&self.plugin_id) {
+                Ok(file) => inner.file = Some(file),
+                Err(e) => {
+                    log::error!("Failed to open log file for {}: {}", self.plugin_id, e);
+                    inner.file_failed = true;
+                }
+            }
+        }
+
+        if let Some(file) = inner.file.as_mut() {
+            let record = format!(
+                "[{}][{}] {}\n",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                match stream {
+                    OutputStream::Stdout => "stdout",
+                    OutputStream::Stderr => "stderr",
+                },
+                line
+            );
+            if let Err(e) = file.write(record.as_bytes()) {
+                log::error!("Failed to write log file for {}: {}", self.plugin_id, e);
+                inner.file = None;
+                inner.file_failed = true;
+            }
+        }
+
+        if inner.recent.len() >= RECENT_LINES {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back(PluginOutputLine {
+            stream,
+            line: line.to_string(),
+            timestamp: Utc::now().timestamp_millis(),
+        });
+    }
+}
+
+/// サイズが上限を超えたら<id>.1.log, <id>.2.log...へずらしていくログファイル
+#[derive(Debug)]
+struct RotatingFile {
+    dir: PathBuf,
+    plugin_id: String,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(plugin_id: &str) -> std::io::Result<Self> {
+        let dir = Directories::get_plugin_log_dir()?;
+        fs::create_dir_all(&dir)?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}.log", plugin_id)))?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            dir,
+            plugin_id: plugin_id.to_string(),
+            file,
+            size,
+        })
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            self.dir.join(format!("{}.log", self.plugin_id))
+        } else {
+            self.dir.join(format!("{}.{}.log", self.plugin_id, index))
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if self.size > 0 && self.size + data.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        // 最も古いものを消し、残りを1つずつずらす
+        let _ = fs::remove_file(self.path(MAX_ROTATED));
+        for index in (0..MAX_ROTATED).rev() {
+            let from = self.path(index);
+            if from.exists() {
+                fs::rename(&from, self.path(index + 1))?;
+            }
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(0))?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::error::ParameterError;
+
+/// アクションのパラメーターの値
+pub type ParameterValues = Map<String, Value>;
+
+/// actions.jsonで宣言するアクションのパラメーター
+/// JSON Schemaのキーワードの一部に従う
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionParameter {
+    /// パラメーター名。マッピングに保存される値のキーになる
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParameterType,
+    /// 表示名
+    pub title: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    /// 値が設定されていない時に使う値
+    pub default: Option<Value>,
+    /// 取りうる値の一覧
+    #[serde(rename = "enum")]
+    pub choices: Option<Vec<Value>>,
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ParameterType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+}
+
+impl ActionParameter {
+    fn check(&self, value: &Value) -> Result<(), ParameterError> {
+        let type_matched = match self.kind {
+            ParameterType::String => value.is_string(),
+            ParameterType::Number => value.is_number(),
+            ParameterType::Integer => value.is_i64() || value.is_u64(),
+            ParameterType::Boolean => value.is_boolean(),
+        };
+        if !type_matched {
+            return Err(ParameterError::TypeMismatch {
+                name: self.name.clone(),
+                expected: self.kind,
+            });
+        }
+
+        if let Some(choices) = &self.choices {
+            if !choices.contains(value) {
+                return Err(ParameterError::NotInEnum(self.name.clone()));
+            }
+        }
+
+        if let Some(number) = value.as_f64() {
+            let below = self.minimum.is_some_and(|minimum| number < minimum);
+            let above = self.maximum.is_some_and(|maximum| number > maximum);
+            if below || above {
+                return Err(ParameterError::OutOfRange(self.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// パラメーターの値を宣言に従って検証し、省略された値をデフォルト値で埋めて返す
+pub fn validate(
+    specs: &[ActionParameter],
+    values: &ParameterValues,
+) -> Result<ParameterValues, ParameterError> {
+    if let Some(name) = values
+        .keys()
+        .find(|name| !specs.iter().any(|spec| &spec.name == *name))
+    {
+        return Err(ParameterError::Unknown(name.clone()));
+    }
+
+    let mut validated = ParameterValues::new();
+    for spec in specs {
+        let value = match values.get(&spec.name).filter(|value| !value.is_null()) {
+            Some(value) => value,
+            None => match &spec.default {
+                Some(default) => default,
+                None if spec.required => return Err(ParameterError::Missing(spec.name.clone())),
+                None => continue,
+            },
+        };
+
+        spec.check(value)?;
+        validated.insert(spec.name.clone(), value.clone());
+    }
+
+    Ok(validated)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn specs() -> Vec<ActionParameter> {
+        serde_json::from_value(json!([
+            { "name": "text", "type": "string", "required": true },
+            { "name": "volume", "type": "integer", "minimum": 0, "maximum": 100, "default": 50 },
+            { "name": "mode", "type": "string", "enum": ["toggle", "hold"] },
+            { "name": "enabled", "type": "boolean" }
+        ]))
+        .unwrap()
+    }
+
+    fn values(value: Value) -> ParameterValues {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn validate_fills_defaults() {
+        let validated = validate(&specs(), &values(json!({ "text": "hello" }))).unwrap();
+
+        assert_eq!(validated, values(json!({ "text": "hello", "volume": 50 })));
+    }
+
+    #[test]
+    fn validate_treats_null_as_missing() {
+        let validated =
+            validate(&specs(), &values(json!({ "text": "a", "volume": null }))).unwrap();
+
+        assert_eq!(validated["volume"], json!(50));
+    }
+
+    #[test]
+    fn validate_rejects_unknown_parameter() {
+        let result = validate(&specs(), &values(json!({ "text": "a", "other": 1 })));
+
+        assert!(matches!(result, Err(ParameterError::Unknown(name)) if name == "other"));
+    }
+
+    #[test]
+    fn validate_rejects_missing_required() {
+        let result = validate(&specs(), &values(json!({ "volume": 10 })));
+
+        assert!(matches!(result, Err(ParameterError::Missing(name)) if name == "text"));
+    }
+
+    #[test]
+    fn validate_rejects_type_mismatch() {
+        let result = validate(&specs(), &values(json!({ "text": "a", "volume": 1.5 })));
+        assert!(matches!(
+            result,
+            Err(ParameterError::TypeMismatch { expected: ParameterType::Integer, .. })
+        ));
+
+        let result = validate(&specs(), &values(json!({ "text": "a", "enabled": "yes" })));
+        assert!(matches!(
+            result,
+            Err(ParameterError::TypeMismatch { expected: ParameterType::Boolean, .. })
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_value_not_in_enum() {
+        let result = validate(&specs(), &values(json!({ "text": "a", "mode": "press" })));
+
+        assert!(matches!(result, Err(ParameterError::NotInEnum(name)) if name == "mode"));
+    }
+
+    #[test]
+    fn validate_checks_range() {
+        for volume in [0, 100] {
+            assert!(validate(&specs(), &values(json!({ "text": "a", "volume": volume }))).is_ok());
+        }
+        for volume in [-1, 101] {
+            let result = validate(&specs(), &values(json!({ "text": "a", "volume": volume })));
+            assert!(matches!(result, Err(ParameterError::OutOfRange(name)) if name == "volume"));
+        }
+    }
+}
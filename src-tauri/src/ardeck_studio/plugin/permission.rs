@@ -0,0 +1,41 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde::{Deserialize, Serialize};
+
+/// プラグインがホストに要求できる操作の権限
+/// マニフェストで宣言し、ユーザーが許可したものだけを使える
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PluginPermission {
+    /// デバイスの一覧やスイッチの状態を読む
+    #[serde(rename = "state.read")]
+    StateRead,
+    /// デバイスへフィードバックを送る
+    #[serde(rename = "device.write")]
+    DeviceWrite,
+    /// 仮想の入力デバイスを作り、スイッチの入力を送る
+    #[serde(rename = "device.virtual")]
+    DeviceVirtual,
+    /// デバイスのマッピングプリセットを切り替える
+    #[serde(rename = "preset.switch")]
+    PresetSwitch,
+    /// ユーザーに通知する
+    #[serde(rename = "notification.show")]
+    NotificationShow,
+}
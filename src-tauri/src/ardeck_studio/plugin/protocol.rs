@@ -0,0 +1,130 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use semver::Version;
+
+use super::error::HandshakeError;
+
+/// アプリケーションのバージョン
+pub const ARDECK_STUDIO_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// このホストが話すプラグインプロトコルのバージョン
+/// 0.1.0: Helloにtokenを追加
+/// 0.2.0: プラグインからホストへのRequest/Responseを追加
+/// 0.3.0: プラグインの設定の読み書きとConfigChangedを追加
+/// 0.4.0: ActionにactionIdを付け、ActionResultを追加
+/// 0.5.0: セッション中のアクションの登録と削除を追加
+/// 0.6.0: 仮想デバイスを追加
+/// 0.7.0: イベントの購読を追加
+pub const PROTOCOL_VERSION: Version = Version::new(0, 7, 0);
+
+/// 対応する最も古いプラグインプロトコルのバージョン
+/// 0.0.xのプラグインはHelloでトークンを送らず認証できないため、意図して対応しない
+pub const MIN_PROTOCOL_VERSION: Version = Version::new(0, 1, 0);
+
+/// ConfigChangedを受け取れるプロトコルのバージョン
+pub const CONFIG_CHANGED_SINCE: Version = Version::new(0, 3, 0);
+
+/// ActionResultで結果を返すプロトコルのバージョン
+pub const ACTION_RESULT_SINCE: Version = Version::new(0, 4, 0);
+
+/// 購読したイベントを受け取れるプロトコルのバージョン
+pub const EVENTS_SINCE: Version = Version::new(0, 7, 0);
+
+/// プラグインが話すプロトコルのバージョンから、セッションで使うバージョンを決める
+///
+/// プラグインの方が古ければプラグインのバージョンに合わせる。
+/// プラグインの方が新しい場合は、メジャーバージョンが同じならホストのバージョンを使う。
+/// 0.xのマイナーバージョンは機能の追加だけなので、新しいプラグインも古い機能で話せる。
+pub fn negotiate(plugin_version: &str) -> Result<Version, HandshakeError> {
+    let plugin = Version::parse(plugin_version).map_err(|source| {
+        HandshakeError::InvalidVersion {
+            version: plugin_version.to_string(),
+            source,
+        }
+    })?;
+    let incompatible = || HandshakeError::IncompatibleVersion {
+        version: plugin_version.to_string(),
+        min: MIN_PROTOCOL_VERSION,
+        max: PROTOCOL_VERSION,
+    };
+
+    if plugin < MIN_PROTOCOL_VERSION {
+        return Err(incompatible());
+    }
+
+    if plugin <= PROTOCOL_VERSION {
+        return Ok(plugin);
+    }
+
+    // 新しいプラグインは、メジャーバージョンが同じならホストのバージョンに合わせてもらう
+    if plugin.major == PROTOCOL_VERSION.major {
+        Ok(PROTOCOL_VERSION)
+    } else {
+        Err(incompatible())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_accepts_supported_versions() {
+        let cases = [
+            ("0.1.0", "0.1.0"),
+            ("0.4.2", "0.4.2"),
+            ("0.7.0", "0.7.0"),
+            // ホストより新しいものはホストに合わせる
+            ("0.7.3", "0.7.0"),
+            ("0.8.0", "0.7.0"),
+            ("0.12.1", "0.7.0"),
+        ];
+
+        for (plugin, expected) in cases {
+            assert_eq!(
+                negotiate(plugin).unwrap(),
+                Version::parse(expected).unwrap(),
+                "plugin {}",
+                plugin
+            );
+        }
+    }
+
+    #[test]
+    fn negotiate_rejects_unsupported_versions() {
+        for plugin in ["0.0.1", "0.0.9", "1.0.0", "2.3.4"] {
+            assert!(
+                matches!(negotiate(plugin), Err(HandshakeError::IncompatibleVersion { .. })),
+                "plugin {}",
+                plugin
+            );
+        }
+    }
+
+    #[test]
+    fn negotiate_rejects_invalid_versions() {
+        for plugin in ["", "0.1", "abc"] {
+            assert!(
+                matches!(negotiate(plugin), Err(HandshakeError::InvalidVersion { .. })),
+                "plugin {}",
+                plugin
+            );
+        }
+    }
+}
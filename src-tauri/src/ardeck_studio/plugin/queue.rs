@@ -0,0 +1,182 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{collections::VecDeque, time::Duration};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::ardeck_studio::action::Action;
+
+/// プラグインごとに待たせておけるアクションの数
+const QUEUE_CAPACITY: usize = 32;
+/// キューに入れてから送るまでの期限。過ぎたアクションは捨てる
+const QUEUE_MAX_AGE: Duration = Duration::from_secs(5);
+
+/// キューの統計
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueMetrics {
+    /// キューに入れた数
+    pub queued: u64,
+    /// セッション開始後に送った数
+    pub flushed: u64,
+    /// キューがいっぱいで捨てた数
+    pub overflowed: u64,
+    /// 期限が過ぎて捨てた数
+    pub expired: u64,
+}
+
+/// セッションが始まる前に発生したアクションを、Helloが完了するまで待たせるキュー
+#[derive(Debug, Default)]
+pub struct OutboundQueue {
+    actions: VecDeque<(Instant, Action)>,
+    metrics: QueueMetrics,
+}
+
+impl OutboundQueue {
+    /// アクションを追加する
+    /// いっぱいの場合は最も古いものを捨てる
+    pub fn push(&mut self, action: Action, now: Instant) {
+        self.expire(now);
+
+        if self.actions.len() >= QUEUE_CAPACITY {
+            self.actions.pop_front();
+            self.metrics.overflowed += 1;
+            log::warn!("Outbound queue is full. Dropped the oldest action.");
+        }
+
+        self.actions.push_back((now, action));
+        self.metrics.queued += 1;
+    }
+
+    /// 期限内のアクションを古い順に全て取り出す
+    pub fn drain(&mut self, now: Instant) -> Vec<Action> {
+        self.expire(now);
+
+        let actions: Vec<Action> = self.actions.drain(..).map(|(_, action)| action).collect();
+        self.metrics.flushed += actions.len() as u64;
+
+        actions
+    }
+
+    /// 期限が過ぎたアクションを捨てる
+    pub fn expire(&mut self, now: Instant) {
+        while let Some((queued_at, _)) = self.actions.front() {
+            if now.duration_since(*queued_at) <= QUEUE_MAX_AGE {
+                break;
+            }
+
+            self.actions.pop_front();
+            self.metrics.expired += 1;
+            log::warn!("Dropped an action that waited more than {:?}", QUEUE_MAX_AGE);
+        }
+    }
+
+    /// 待っているアクションを全て捨てる。停止した時など、送る見込みがない場合に使う
+    pub fn clear(&mut self) {
+        self.actions.clear();
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ardeck_studio::{
+        action::action_target::ActionTarget, plugin::parameter::ParameterValues,
+        switch_info::SwitchInfo,
+    };
+
+    use super::*;
+
+    fn action(id: usize) -> Action {
+        Action {
+            switch: SwitchInfo::new(),
+            target: ActionTarget {
+                plugin_id: "test".to_string(),
+                action_id: id.to_string(),
+                parameters: ParameterValues::new(),
+            },
+            action_id: None,
+        }
+    }
+
+    fn ids(actions: &[Action]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|action| action.target.action_id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn drain_returns_actions_in_order() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::default();
+        for id in 0..3 {
+            queue.push(action(id), now);
+        }
+
+        assert_eq!(ids(&queue.drain(now)), ["0", "1", "2"]);
+        assert!(queue.drain(now).is_empty());
+
+        let metrics = queue.metrics();
+        assert_eq!((metrics.queued, metrics.flushed), (3, 3));
+    }
+
+    #[test]
+    fn push_drops_oldest_when_full() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::default();
+        for id in 0..QUEUE_CAPACITY + 2 {
+            queue.push(action(id), now);
+        }
+
+        let drained = queue.drain(now);
+        assert_eq!(drained.len(), QUEUE_CAPACITY);
+        assert_eq!(drained[0].target.action_id, "2");
+        assert_eq!(queue.metrics().overflowed, 2);
+    }
+
+    #[test]
+    fn drain_drops_expired_actions() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::default();
+        queue.push(action(0), now);
+        queue.push(action(1), now + Duration::from_secs(2));
+
+        let later = now + QUEUE_MAX_AGE + Duration::from_secs(1);
+        assert_eq!(ids(&queue.drain(later)), ["1"]);
+        assert_eq!(queue.metrics().expired, 1);
+    }
+
+    #[test]
+    fn clear_does_not_count_as_dropped() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::default();
+        queue.push(action(0), now);
+        queue.clear();
+
+        assert!(queue.drain(now).is_empty());
+        let metrics = queue.metrics();
+        assert_eq!((metrics.flushed, metrics.overflowed, metrics.expired), (0, 0, 0));
+    }
+}
@@ -0,0 +1,95 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ardeck_studio::settings::{
+    core::get_studio_config, definitions::ardeck_studio::RuntimeConfig,
+};
+
+use super::error::LoadError;
+
+/// mainを実行するインタプリタ
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PluginRuntime {
+    Node,
+    Bun,
+    Python,
+}
+
+impl PluginRuntime {
+    /// 設定されたインタプリタのコマンド名かパス
+    fn interpreter(self, config: &RuntimeConfig) -> &str {
+        match self {
+            Self::Node => &config.node,
+            Self::Bun => &config.bun,
+            Self::Python => &config.python,
+        }
+    }
+}
+
+/// 設定からインタプリタを探し、実行ファイルのパスを返す
+pub async fn resolve(runtime: PluginRuntime) -> Result<PathBuf, LoadError> {
+    let config = match get_studio_config().await {
+        Ok(config) => config.runtimes,
+        Err(e) => {
+            log::error!("Failed to load studio config. Using default: {}", e);
+            RuntimeConfig::default()
+        }
+    };
+
+    let interpreter = runtime.interpreter(&config);
+
+    find_executable(interpreter).ok_or_else(|| LoadError::RuntimeNotFound {
+        runtime,
+        interpreter: interpreter.to_string(),
+    })
+}
+
+/// パスが指定されていればそのファイルを、コマンド名ならPATHから探す
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() || path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+
+    let paths = env::var_os("PATH")?;
+    for dir in env::split_paths(&paths) {
+        let candidate = dir.join(name);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+
+        #[cfg(windows)]
+        {
+            let candidate = candidate.with_extension("exe");
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+    }
+
+    None
+}
@@ -16,38 +16,83 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::fs::File;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use futures_util::stream::SplitSink;
+use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
-use serialport::SerialPortInfo;
+use notify::RecommendedWatcher;
 use tokio::sync::Mutex;
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::channel;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Utf8Bytes;
 use tokio_tungstenite::WebSocketStream;
 use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
 
 use crate::ardeck_studio::action::Action;
-use crate::ardeck_studio::ardeck::tauri::get_device_id;
-use crate::ardeck_studio::settings::tauri::_get_ardeck_profile;
+use crate::ardeck_studio::ardeck::core::ArdeckCore;
+use crate::ardeck_studio::error::ErrorCode;
+use crate::ardeck_studio::settings::core::{
+    get_ardeck_profile, get_granted_permissions, get_reviewed_permissions, get_studio_config,
+    is_plugin_enabled, set_granted_permissions, set_plugin_enabled,
+};
+use crate::ardeck_studio::settings::definitions::ardeck_studio::PluginServerConfig;
 use crate::ardeck_studio::switch_info::SwitchInfo;
 use crate::service::dir::Directories;
 
+use super::error::{HandshakeError, InstallError, LoadError, PluginError, PluginLoadError};
+use super::install::{self, StagedPlugin};
 use super::manager::PluginManager;
+use super::manifest;
+use super::permission::PluginPermission;
+use super::runtime;
+use super::supervisor::{self, CrashHistory, PluginStatus};
+use super::watcher;
+use super::{host, parameter, protocol};
 
-use super::{Plugin, PluginAction, PluginManifestJSON, PluginMessage};
+use super::{Plugin, PluginAction, PluginInfo, PluginManifestJSON, PluginMessage, PluginStopper};
 
 // static PLUGIN_MANAGER: Lazy<Mutex<PluginManager>> = Lazy::new(|| Mutex::new(PluginManager::new()));
 
 pub type PluginServerSink = SplitSink<WebSocketStream<TcpStream>, Message>;
+type PluginServerStream = SplitStream<WebSocketStream<TcpStream>>;
+
+/// 接続からHelloが届くまでの待ち時間
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// インストール後、ファイルの変更による再読み込みをしない時間
+const RELOAD_SUPPRESS: Duration = Duration::from_secs(2);
 
 pub struct PluginServer {
     plugin_manager: Arc<Mutex<PluginManager>>,
     listener: Option<tokio::task::JoinHandle<()>>,
+    addr: Option<SocketAddr>,
+    load_errors: Vec<PluginLoadError>,
+    supervisor: Option<tokio::task::JoinHandle<()>>,
+    watcher: Option<RecommendedWatcher>,
+    /// インストールしたプラグインのディレクトリと、再読み込みしない期限
+    reload_suppressed: HashMap<PathBuf, Instant>,
+}
+
+/// begin_reloadで準備した、読み込み直すディレクトリ
+pub struct DirReload {
+    dir: PathBuf,
+    removed: bool,
+    /// 登録を解除したプラグイン。finish_reloadの前に止める
+    pub stopper: Option<PluginStopper>,
+}
+
+/// begin_installで準備した、入れ替える場所
+pub struct PendingInstall {
+    target: PathBuf,
+    server_port: u16,
+    /// 入れ替えるプラグイン。finish_installの前に止める
+    pub stopper: Option<PluginStopper>,
 }
 
 impl PluginServer {
@@ -55,150 +100,541 @@ impl PluginServer {
         Self {
             plugin_manager: Arc::new(Mutex::new(PluginManager::new())),
             listener: None,
+            addr: None,
+            load_errors: Vec::new(),
+            supervisor: None,
+            watcher: None,
+            reload_suppressed: HashMap::new(),
         }
     }
 
-    pub async fn start(&mut self) -> std::io::Result<()> {
-        let plugin_manager = Arc::clone(&self.plugin_manager);
+    /// 設定されたアドレスで待ち受けを開始し、実際に待ち受けているアドレスを返す
+    pub async fn start(&mut self) -> std::io::Result<SocketAddr> {
+        let config = match get_studio_config().await {
+            Ok(config) => config.plugin_server,
+            Err(e) => {
+                log::error!("Failed to load studio config. Using default: {}", e);
+                PluginServerConfig::default()
+            }
+        };
+
+        let tcp = bind(&config).await?;
+        let addr = tcp.local_addr()?;
+        self.addr = Some(addr);
 
-        let (tx, mut rx) = channel::<bool>(100);
+        log::info!("Plugin server listening on {}", addr);
 
-        // 起動
+        let plugin_manager = Arc::clone(&self.plugin_manager);
+        let heartbeat = Heartbeat {
+            interval: config.ping_interval(),
+            timeout: config.pong_timeout(),
+        };
+
+        // 接続待ち
         self.listener = Some(tokio::spawn(async move {
-            // TODO: ポート番号を設定可能にする
-            let tcp = TcpListener::bind("127.0.0.1:6725").await.unwrap();
+            loop {
+                let (stream, peer) = match tcp.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        log::error!("Failed to accept plugin connection: {}", e);
+                        continue;
+                    }
+                };
 
-            // 起動失敗
-            if let Err(_) = tx.send(true).await {
-                return;
+                tokio::spawn(handle_connection(
+                    peer,
+                    stream,
+                    plugin_manager.clone(),
+                    heartbeat,
+                ));
             }
+        }));
 
-            log::debug!("listening on {}", tcp.local_addr().unwrap());
+        Ok(addr)
+    }
 
-            // 接続待ち
-            while let Ok((stream, _)) = tcp.accept().await {
-                let peer = stream
-                    .peer_addr()
-                    .expect("connected streams should have a peer address");
+    /// 最後に読み込んだ時に、読み込めなかったプラグインの一覧
+    pub fn get_load_errors(&self) -> Vec<PluginLoadError> {
+        self.load_errors.clone()
+    }
 
-                tokio::spawn(handle_connection(peer, stream, plugin_manager.clone()));
-            }
-        }));
+    pub async fn get_plugin_manager(&self) -> Arc<Mutex<PluginManager>> {
+        Arc::clone(&self.plugin_manager)
+    }
 
-        // 起動待ち
-        while let Some(b) = rx.recv().await {
-            if b {
-                log::debug!("plugin server OK.");
+    /// 全てのプラグインに終了を要求し、timeoutまでに終了しなかったものはkillする
+    pub async fn stop_plugin_all(&self, timeout: Duration) {
+        log::info!("Stopping plugin all...");
 
-                return Ok(());
-            } else {
-                log::debug!("plugin server failed.");
+        // 終了を待つ間マネージャーのロックを持たないよう、取り出してから止める
+        let mut stoppers: Vec<PluginStopper> = self
+            .plugin_manager
+            .lock()
+            .await
+            .values_mut()
+            .map(|plugin| plugin.take_stopper())
+            .collect();
 
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to start plugin server.",
-                ));
-            }
+        for stopper in stoppers.iter_mut() {
+            stopper.request_shutdown().await;
         }
 
-        log::error!("Failed to start plugin server.");
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Failed to start plugin server.",
-        ))
-    }
+        let deadline = Instant::now() + timeout;
+        for stopper in stoppers {
+            stopper.wait_or_kill(deadline).await;
+        }
 
-    pub async fn get_plugin_manager(&self) -> Arc<Mutex<PluginManager>> {
-        Arc::clone(&self.plugin_manager)
+        log::info!("Plugin all stopped.");
     }
 
-    pub async fn execute_plugin_all(&self) {
+    pub async fn execute_plugin_all(&mut self) {
         log::info!("Executing plugin all...");
 
-        let dir = match Directories::get(Directories::get_plugin_dir().unwrap()) {
+        let server_port = match self.addr {
+            Some(addr) => addr.port(),
+            None => {
+                log::error!("Plugin server has not started yet.");
+                return;
+            }
+        };
+
+        self.load_errors.clear();
+
+        let plugin_dir = match Directories::get_plugin_dir() {
+            Ok(plugin_dir) => plugin_dir,
+            Err(e) => {
+                log::error!("Failed to get plugin dir: {}", e);
+                return;
+            }
+        };
+
+        let dir = match Directories::get(&plugin_dir) {
             Ok(read_dir) => read_dir,
-            Err(_) => {
-                log::error!("Failed to get plugin dir.");
+            Err(e) => {
+                log::error!("Failed to read plugin dir: {}", e);
+                self.load_errors.push(PluginLoadError::new(
+                    &plugin_dir,
+                    None,
+                    &LoadError::ReadDir(e),
+                ));
                 return;
             }
         };
 
         for entry in dir {
-            if entry.is_err() {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    log::error!("Failed to read plugin dir entry: {}", e);
+                    self.load_errors.push(PluginLoadError::new(
+                        &plugin_dir,
+                        None,
+                        &LoadError::ReadDir(e),
+                    ));
+                    continue;
+                }
+            };
+
+            if path.is_file() {
                 continue;
-                // TODO: Error
             }
 
-            let path = entry.unwrap().path();
-
-            if path.is_file() {
+            // インストール中の一時ディレクトリなどは読み込まない
+            if path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+            {
                 continue;
             }
 
-            // マニフェストファイルを取得
-            let manifest_file = match File::open(path.clone().join("manifest.json")) {
-                Ok(file) => file,
-                Err(e) => {
-                    log::error!("Failed to open manifest.json: {}", e);
+            self.load_dir(path, server_port).await;
+        }
 
-                    continue;
-                }
+        log::info!("Plugin all executed.");
+
+        // クラッシュしたプラグインの再起動
+        if self.supervisor.is_none() {
+            self.supervisor = Some(supervisor::start(Arc::clone(&self.plugin_manager)));
+        }
+
+        // 開発中のプラグインの再読み込み
+        if self.watcher.is_none() {
+            match watcher::start(plugin_dir) {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(e) => log::error!("Failed to watch plugin dir: {}", e),
+            }
+        }
+    }
+
+    /// プラグインのディレクトリからマニフェストとアクションを読み込み、登録する
+    /// 読み込めなかった場合はエラーを記録する
+    async fn load_dir(&mut self, path: PathBuf, server_port: u16) {
+        // マニフェストとアクションを読み込み、検証する
+        let (manifest, actions) = match manifest::load(&path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                log::error!("Failed to load plugin {}: {}", path.display(), e);
+                self.load_errors.push(PluginLoadError::from_manifest_error(
+                    &path,
+                    manifest::read_id(&path),
+                    &e,
+                ));
+                return;
+            }
+        };
+
+        // 同じIDのプラグインは最初に見つかったものだけを読み込む
+        if let Some(other) = self.plugin_manager.lock().await.get(&manifest.id) {
+            let e = LoadError::DuplicateId {
+                plugin_id: manifest.id.clone(),
+                other: other.dir.clone(),
             };
+            log::error!("Failed to load plugin {}: {}", path.display(), e);
+            self.load_errors
+                .push(PluginLoadError::new(&path, Some(manifest.id.clone()), &e));
+            return;
+        }
 
-            // アクションファイルを取得
-            let actions_file = match File::open(path.clone().join("actions.json")) {
-                Ok(file) => file,
-                Err(e) => {
-                    log::error!("Failed to open actions.json: {}", e);
+        self.register(manifest, actions, path, server_port).await;
+    }
 
-                    continue;
+    /// 変更のあったプラグインのディレクトリを読み込み直す準備をする
+    /// マニフェスト、アクション、mainが変わった場合のみ登録を解除し、プロセスを再起動する
+    /// 読み込み直さない場合はNoneを返す
+    pub async fn begin_reload(&mut self, dir: &Path, changed: &[PathBuf]) -> Option<DirReload> {
+        self.addr?;
+
+        // インストール直後の変更は、インストール時に読み込み済み
+        if let Some(until) = self.reload_suppressed.get(dir) {
+            if Instant::now() < *until {
+                return None;
+            }
+        }
+        self.reload_suppressed.remove(dir);
+
+        let registered = self
+            .plugin_manager
+            .lock()
+            .await
+            .values()
+            .find(|plugin| plugin.dir == dir)
+            .map(|plugin| {
+                let watched = [
+                    dir.join(manifest::MANIFEST_FILE),
+                    dir.join(manifest::ACTIONS_FILE),
+                    dir.join(&plugin.manifest.main),
+                ];
+                (plugin.manifest.id.clone(), watched)
+            });
+
+        match registered {
+            Some((plugin_id, watched)) => {
+                let removed = !dir.exists();
+                if !removed && !changed.iter().any(|path| watched.contains(path)) {
+                    return None;
+                }
+
+                let stopper = self
+                    .plugin_manager
+                    .lock()
+                    .await
+                    .remove(&plugin_id)
+                    .map(|mut plugin| plugin.take_stopper());
+
+                if removed {
+                    log::info!("Plugin removed: {}", plugin_id);
+                } else {
+                    log::info!("Reloading plugin: {}", plugin_id);
+                }
+
+                Some(DirReload {
+                    dir: dir.to_path_buf(),
+                    removed,
+                    stopper,
+                })
+            }
+            None => {
+                // 新しく置かれたか、前回読み込めなかったプラグイン
+                if !dir.join(manifest::MANIFEST_FILE).is_file() {
+                    return None;
+                }
+
+                log::info!("Loading plugin: {}", dir.display());
+
+                Some(DirReload {
+                    dir: dir.to_path_buf(),
+                    removed: false,
+                    stopper: None,
+                })
+            }
+        }
+    }
+
+    /// begin_reloadで準備したディレクトリを読み込む
+    /// 登録を解除したプラグインは、先に止めておく
+    pub async fn finish_reload(&mut self, reload: DirReload) {
+        let server_port = match self.addr {
+            Some(addr) => addr.port(),
+            None => return,
+        };
+        if reload.removed {
+            return;
+        }
+
+        self.load_errors.retain(|e| e.dir != reload.dir);
+        self.load_dir(reload.dir, server_port).await;
+    }
+
+    /// プラグインを実行し、マネージャーに登録する
+    async fn register(
+        &mut self,
+        manifest: PluginManifestJSON,
+        actions: Vec<PluginAction>,
+        dir: PathBuf,
+        server_port: u16,
+    ) -> PluginInfo {
+        let mut plugin = Plugin::new(manifest.clone(), actions, dir.clone(), server_port);
+        plugin.enabled = is_plugin_enabled(&manifest.id).await;
+        plugin.granted_permissions = get_granted_permissions(&manifest.id).await;
+        plugin.reviewed_permissions = get_reviewed_permissions(&manifest.id).await;
+
+        // 確認していない権限はユーザーに確認してもらう
+        let pending = plugin.pending_permissions();
+        if !pending.is_empty() {
+            log::info!("Plugin {} requests permissions: {:?}", manifest.id, pending);
+        }
+
+        // インタプリタを探す
+        let mut runnable = true;
+        if let Some(runtime) = manifest.runtime {
+            match runtime::resolve(runtime).await {
+                Ok(interpreter) => plugin.interpreter = Some(interpreter),
+                Err(e) => {
+                    log::error!("Failed to load plugin {}: {}", manifest.id, e);
+                    self.load_errors.push(PluginLoadError::new(
+                        &dir,
+                        Some(manifest.id.clone()),
+                        &e,
+                    ));
+                    runnable = false;
                 }
+            }
+        }
+
+        // プラグインを実行
+        if !plugin.enabled {
+            log::info!("Plugin is disabled: {}", manifest.id);
+        } else if !pending.is_empty() {
+            log::info!("Plugin is waiting for permission approval: {}", manifest.id);
+        } else if !runnable {
+            log::info!("Plugin is not started: {}", manifest.id);
+        } else if let Err(source) = plugin.start() {
+            let e = LoadError::Spawn {
+                main: dir.join(&manifest.main),
+                source,
             };
+            self.load_errors
+                .push(PluginLoadError::new(&dir, Some(manifest.id.clone()), &e));
+        }
+        let info = plugin.info();
+
+        // プラグイン情報とプロセスをマネージャーに登録
+        self.plugin_manager
+            .lock()
+            .await
+            .insert(manifest.id.clone(), plugin);
+
+        log::info!("Registered plugin: {}", &manifest.name);
 
-            let manifest: PluginManifestJSON = serde_json::from_reader(manifest_file).unwrap();
-            let actions: Vec<PluginAction> = serde_json::from_reader(actions_file).unwrap();
+        info
+    }
+
+    /// プラグインの有効、無効を切り替えて保存し、プロセスを起動する
+    /// 無効にした場合は一覧に残したままStoppedにし、止めるプラグインを返す
+    pub async fn set_enabled(
+        &mut self,
+        plugin_id: &str,
+        enabled: bool,
+    ) -> Result<(PluginInfo, Option<PluginStopper>), PluginError> {
+        let mut plugin_manager = self.plugin_manager.lock().await;
+        let plugin = plugin_manager
+            .get_mut(plugin_id)
+            .ok_or_else(|| PluginError::NotFound(plugin_id.to_string()))?;
+
+        set_plugin_enabled(plugin_id, enabled).await?;
+
+        if plugin.enabled == enabled {
+            return Ok((plugin.info(), None));
+        }
+        plugin.enabled = enabled;
+
+        if enabled {
+            log::info!("Enabling plugin: {}", plugin_id);
+            plugin.crash_history = CrashHistory::default();
+            if plugin.pending_permissions().is_empty() {
+                // 失敗した場合はCrashedになり、supervisorが再起動する
+                let _ = plugin.start();
+            }
+            return Ok((plugin.info(), None));
+        }
 
-            // プラグインの実行ファイルのパスを取得
-            let plugin_main_path = path.clone().join(manifest.clone().main);
+        log::info!("Disabling plugin: {}", plugin_id);
+        let stopper = plugin.take_stopper();
 
-            log::info!("Executing plugin: {}", &manifest.name);
+        Ok((plugin.info(), Some(stopper)))
+    }
 
-            // プラグインを実行
-            let process = std::process::Command::new(plugin_main_path)
-                .arg("6725")
-                .spawn()
-                .expect("Failed to execute plugin");
+    /// ユーザーが許可した権限を保存する
+    /// マニフェストで宣言されていない権限は無視し、宣言された残りは許可しなかったものとして記録する
+    /// 確認を待っていたプラグインは、ここで起動する
+    pub async fn set_permissions(
+        &mut self,
+        plugin_id: &str,
+        permissions: Vec<PluginPermission>,
+    ) -> Result<PluginInfo, PluginError> {
+        let mut plugin_manager = self.plugin_manager.lock().await;
+        let plugin = plugin_manager
+            .get_mut(plugin_id)
+            .ok_or_else(|| PluginError::NotFound(plugin_id.to_string()))?;
 
-            // プラグイン情報とプロセスをマネージャーに登録
-            match self.plugin_manager.lock().await.insert(
-                manifest.id.clone(),
-                // Plugin::new(manifest, actions, Arc::new(Mutex::new(process))),
-                Plugin::new(manifest.clone(), actions),
-            ) {
-                None => (),
-                Some(_) => (),
+        let mut granted: Vec<PluginPermission> = Vec::new();
+        for permission in permissions {
+            if plugin.manifest.permissions.contains(&permission) && !granted.contains(&permission) {
+                granted.push(permission);
             }
+        }
+
+        let was_pending = !plugin.pending_permissions().is_empty();
+        let reviewed = plugin.manifest.permissions.clone();
+        set_granted_permissions(plugin_id, granted.clone(), reviewed.clone()).await?;
 
-            log::info!("Registered plugin: {}", &manifest.name);
+        log::info!("Plugin {} permissions granted: {:?}", plugin_id, granted);
+        plugin.granted_permissions = granted;
+        plugin.reviewed_permissions = reviewed;
+
+        if was_pending && plugin.enabled && plugin.process.is_none() {
+            log::info!("Starting approved plugin: {}", plugin_id);
+            let _ = plugin.start();
         }
 
-        log::info!("Plugin all executed.");
+        Ok(plugin.info())
+    }
+
+    /// 展開済みのプラグインを入れ替える準備をする
+    /// 同じIDのプラグインがあれば一覧に残したままStoppedにし、止めるプラグインを返す
+    pub async fn begin_install(
+        &mut self,
+        staged: &StagedPlugin,
+    ) -> Result<PendingInstall, InstallError> {
+        let server_port = self.addr.ok_or(InstallError::ServerNotStarted)?.port();
+        let plugin_id = &staged.manifest.id;
+
+        // 同じIDのプラグインがあれば、同じ場所に入れ替える
+        let plugin_dir = Directories::get_plugin_dir().map_err(|source| InstallError::Io {
+            path: PathBuf::from("plugins"),
+            source,
+        })?;
+        let mut plugin_manager = self.plugin_manager.lock().await;
+        let existing = plugin_manager.get_mut(plugin_id);
+        let target = install::resolve_target(
+            &plugin_dir,
+            plugin_id,
+            existing.as_ref().map(|plugin| plugin.dir.as_path()),
+        )?;
+
+        let stopper = existing.map(|plugin| {
+            log::info!("Replacing plugin: {}", plugin_id);
+            plugin.take_stopper()
+        });
+
+        Ok(PendingInstall {
+            target,
+            server_port,
+            stopper,
+        })
+    }
+
+    /// 展開済みのプラグインをプラグインディレクトリへ移し、起動する
+    /// 入れ替えるプラグインは、先に止めておく。移せなかった場合は起動し直す
+    /// 確認していない権限があれば、set_permissionsで許可されるまで起動しない
+    pub async fn finish_install(
+        &mut self,
+        staged: &StagedPlugin,
+        pending: PendingInstall,
+    ) -> Result<PluginInfo, InstallError> {
+        let plugin_id = staged.manifest.id.clone();
+        let target = pending.target;
+
+        // 入れ替えで起きるファイルの変更では再読み込みしない
+        self.reload_suppressed
+            .insert(target.clone(), Instant::now() + RELOAD_SUPPRESS);
+
+        if let Err(e) = install::swap_in(staged, &target) {
+            // 元のファイルに戻しているため、止めたプラグインを起動し直す
+            if let Some(plugin) = self.plugin_manager.lock().await.get_mut(&plugin_id) {
+                if plugin.enabled && plugin.pending_permissions().is_empty() {
+                    log::info!("Restarting replaced plugin: {}", plugin_id);
+                    // 失敗した場合はCrashedになり、supervisorが再起動する
+                    let _ = plugin.start();
+                }
+            }
+            return Err(e);
+        }
+
+        // 入れ替えたプラグインの以前のエラーは消す
+        self.load_errors
+            .retain(|e| e.plugin_id.as_deref() != Some(plugin_id.as_str()) && e.dir != target);
+
+        log::info!("Installed plugin: {} -> {}", plugin_id, target.display());
+
+        Ok(self
+            .register(
+                staged.manifest.clone(),
+                staged.actions.clone(),
+                target,
+                pending.server_port,
+            )
+            .await)
     }
 
-    pub async fn put_action(&mut self, port_info: SerialPortInfo, switch_info: SwitchInfo) {
+    /// アンインストールするプラグインの登録を解除する
+    /// 止めてからファイルを削除するのは呼び出し側で行う
+    pub async fn unregister(&mut self, plugin_id: &str) -> Result<Plugin, InstallError> {
+        self.plugin_manager
+            .lock()
+            .await
+            .remove(plugin_id)
+            .ok_or_else(|| InstallError::NotInstalled(plugin_id.to_string()))
+    }
+
+    pub async fn put_action(&mut self, device_id: &str, switch_info: SwitchInfo) {
         // TODO: switch_typeとswitch_idからマッピングの設定を見つけ、そのプラグインに（あれば）put_actionする
 
         // デバイスのプロファイルを取得し、その中からスイッチ情報に対応するアクションを取得
-        let device_profile = _get_ardeck_profile(&get_device_id(port_info).unwrap())
-            .await
-            .unwrap()
-            .unwrap();
+        let device_profile = match get_ardeck_profile(device_id).await {
+            Ok(Some(profile)) => profile,
+            Ok(None) => {
+                log::debug!("\t[plugin.server]: put_action: no profile: {}", device_id);
+                return;
+            }
+            Err(e) => {
+                log::error!("Failed to load ardeck profile: {}", e);
+                return;
+            }
+        };
 
-        let actions = Action::from_switch_info_with_preset_id(
-            switch_info,
-            device_profile.mapping_preset.unwrap(),
-        )
-        .await;
+        let mapping_preset = match device_profile.mapping_preset {
+            Some(mapping_preset) => mapping_preset,
+            None => {
+                log::debug!(
+                    "\t[plugin.server]: put_action: no mapping preset: {}",
+                    device_id
+                );
+                return;
+            }
+        };
+
+        let actions = Action::from_switch_info_with_preset_id(switch_info, mapping_preset).await;
 
         // actionsのtargetの中で、読み込まれているプラグインがあれば、プラグインに渡す
         for action in actions.iter() {
@@ -208,15 +644,42 @@ impl PluginServer {
                 .await
                 .get_mut(&action.target.plugin_id)
             {
+                Some(plugin) if !plugin.enabled => {
+                    log::debug!(
+                        "\t[plugin.server]: put_action: plugin disabled: {}",
+                        action.target.plugin_id
+                    );
+                }
                 Some(plugin) => {
                     log::debug!(
                         "\t[plugin.server]: put_action: plugin found: {}",
                         action.target.plugin_id
                     );
 
-                    // デバイス
+                    // 保存後にプラグインが更新されている場合があるため、送る前にも検証する
+                    let mut action = action.clone();
+                    if let Some(spec) = plugin
+                        .actions
+                        .iter()
+                        .find(|a| a.id == action.target.action_id)
+                    {
+                        match parameter::validate(&spec.parameters, &action.target.parameters) {
+                            Ok(parameters) => action.target.parameters = parameters,
+                            Err(e) => {
+                                log::error!(
+                                    "Invalid parameters for {}/{}: {}",
+                                    action.target.plugin_id,
+                                    action.target.action_id,
+                                    e
+                                );
+                                continue;
+                            }
+                        }
+                    }
 
-                    plugin.send_action(action.clone()).await;
+                    if let Err(e) = plugin.send_action(action).await {
+                        log::error!("Failed to send action: {}", e);
+                    }
                 }
                 None => log::debug!("\t[plugin.server]: put_action: plugin not found"),
             }
@@ -224,63 +687,364 @@ impl PluginServer {
     }
 }
 
+/// 設定されたアドレスで待ち受ける
+/// ポートが使用中などで使えない場合、設定によってはOSが割り当てたポートで待ち受ける
+async fn bind(config: &PluginServerConfig) -> std::io::Result<TcpListener> {
+    match TcpListener::bind((config.host.as_str(), config.port)).await {
+        Ok(tcp) => Ok(tcp),
+        Err(e)
+            if config.port_fallback
+                && config.port != 0
+                && matches!(
+                    e.kind(),
+                    std::io::ErrorKind::AddrInUse | std::io::ErrorKind::PermissionDenied
+                ) =>
+        {
+            log::warn!(
+                "Failed to bind {}:{}: {}. Falling back to an ephemeral port.",
+                config.host,
+                config.port,
+                e
+            );
+            TcpListener::bind((config.host.as_str(), 0)).await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// セッションの生存確認の設定
+#[derive(Clone, Copy, Debug)]
+struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+}
+
 // セッション
 async fn handle_connection(
     peer: SocketAddr,
     stream: TcpStream,
     plugin_manager: Arc<Mutex<PluginManager>>,
+    heartbeat: Heartbeat,
 ) {
-    let ws_stream = accept_async(stream).await.expect("Failed to accept");
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            log::warn!("Failed to accept plugin connection from {}: {}", peer, e);
+            return;
+        }
+    };
 
     let (sink, mut stream) = ws_stream.split();
     let sink_arc = Arc::new(Mutex::new(sink));
 
-    while let Some(msg) = stream.next().await {
-        if let Ok(msg) = msg {
-            if msg.is_text() {
-                let msg_str = msg.to_text().unwrap();
-
-                log::debug!("Received: {}", msg_str);
-
-                let message: PluginMessage = serde_json::from_str(msg_str).unwrap();
-
-                match message {
-                    PluginMessage::Hello {
-                        plugin_version,
-                        ardeck_plugin_web_socket_version,
-                        plugin_id,
-                    } => {
-                        log::debug!("Hello:\n\t{}", plugin_id);
-
-                        // TODO: tauri.conf.jsonからのバージョン情報の取得
-                        let data = PluginMessage::Success {
-                            ardeck_studio_version: "0.2.0".to_string(),
-                            ardeck_studio_web_socket_version: "0.0.1".to_string(),
-                        };
-
-                        sink_arc
-                            .lock()
-                            .await
-                            .send(Message::Text(Utf8Bytes::from(
-                                &serde_json::to_string(&data).unwrap(),
-                            )))
-                            .await
-                            .unwrap();
-
-                        let mut plugin = plugin_manager.lock().await;
-                        plugin
-                            .get_mut(&plugin_id)
-                            .unwrap()
-                            .set_server_sink(sink_arc.clone());
-
-                        log::info!("\t[plugin.server]: plugin session started: {}", plugin_id);
+    // 最初のHelloでプラグインを認証する
+    let handshake = tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        handshake(&mut stream, &sink_arc, &plugin_manager),
+    )
+    .await
+    .unwrap_or(Err(HandshakeError::Timeout));
+
+    let plugin_id = match handshake {
+        Ok(plugin_id) => plugin_id,
+        Err(e) => {
+            log::warn!("Rejected plugin connection from {}: {}", peer, e);
+
+            if !matches!(e, HandshakeError::Closed) {
+                let frame = CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: close_reason(&e),
+                };
+                let _ = sink_arc
+                    .lock()
+                    .await
+                    .send(Message::Close(Some(frame)))
+                    .await;
+            }
+            return;
+        }
+    };
+
+    log::info!("\t[plugin.server]: plugin session started: {}", plugin_id);
+
+    let last_pong = Arc::new(Mutex::new(Instant::now()));
+
+    let receive = async {
+        while let Some(msg) = stream.next().await {
+            if let Ok(msg) = msg {
+                if msg.is_pong() {
+                    *last_pong.lock().await = Instant::now();
+                    continue;
+                }
+
+                if msg.is_text() {
+                    let msg_str = msg.to_text().unwrap();
+
+                    log::debug!("Received: {}", msg_str);
+
+                    let message: PluginMessage = match serde_json::from_str(msg_str) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            log::warn!("Invalid message from plugin {}: {}", plugin_id, e);
+
+                            if let Some(response) = host::invalid_request(msg_str, e) {
+                                send_response(&sink_arc, &response).await;
+                            }
+                            continue;
+                        }
+                    };
+
+                    match message {
+                        PluginMessage::Hello { .. } => {
+                            log::warn!("Plugin {} sent Hello twice. Ignored.", plugin_id);
+                        }
+                        PluginMessage::Request {
+                            message_id,
+                            request,
+                        } => {
+                            // 宣言され、許可された権限の要求だけを処理する
+                            if let Some(permission) = request.required_permission() {
+                                let allowed = plugin_manager
+                                    .lock()
+                                    .await
+                                    .get(&plugin_id)
+                                    .is_some_and(|plugin| plugin.has_permission(permission));
+
+                                if !allowed {
+                                    log::warn!(
+                                        "Plugin {} is not allowed to use {:?}",
+                                        plugin_id,
+                                        permission
+                                    );
+                                    let response = host::permission_denied(message_id, permission);
+                                    send_response(&sink_arc, &response).await;
+                                    continue;
+                                }
+                            }
+
+                            // 要求の処理中も次のメッセージを受け取れるようにする
+                            let plugin_id = plugin_id.clone();
+                            let sink = sink_arc.clone();
+                            tokio::spawn(async move {
+                                let response =
+                                    host::handle_request(&plugin_id, message_id, request).await;
+                                send_response(&sink, &response).await;
+                            });
+                        }
+                        PluginMessage::Subscribe {
+                            message_id,
+                            subscriptions,
+                        } => {
+                            let response = match plugin_manager.lock().await.get_mut(&plugin_id) {
+                                Some(plugin)
+                                    if !plugin.has_permission(PluginPermission::StateRead) =>
+                                {
+                                    host::permission_denied(message_id, PluginPermission::StateRead)
+                                }
+                                Some(plugin) => {
+                                    log::debug!(
+                                        "Plugin {} subscribed: {:?}",
+                                        plugin_id,
+                                        subscriptions
+                                    );
+                                    plugin.subscribe(subscriptions);
+                                    host::done(message_id)
+                                }
+                                None => continue,
+                            };
+                            send_response(&sink_arc, &response).await;
+                        }
+                        PluginMessage::Unsubscribe {
+                            message_id,
+                            subscriptions,
+                        } => {
+                            if let Some(plugin) = plugin_manager.lock().await.get_mut(&plugin_id) {
+                                plugin.unsubscribe(&subscriptions);
+                            }
+                            send_response(&sink_arc, &host::done(message_id)).await;
+                        }
+                        PluginMessage::ActionResult {
+                            action_id,
+                            success,
+                            message,
+                        } => {
+                            if let Some(plugin) = plugin_manager.lock().await.get_mut(&plugin_id) {
+                                plugin.action_result(&action_id, success, message);
+                            }
+                        }
+                        // PluginMessageData::Success { .. } => (),
+                        PluginMessage::Message { .. } => (),
+                        // PluginMessageData::Action { .. } => (),
+                        _ => (),
                     }
-                    // PluginMessageData::Success { .. } => (),
-                    PluginMessage::Message { .. } => (),
-                    // PluginMessageData::Action { .. } => (),
-                    _ => (),
                 }
             }
         }
+    };
+
+    // 応答がなくなったプラグインは、受信を待たずにセッションを閉じる
+    tokio::select! {
+        _ = receive => (),
+        _ = keep_alive(&sink_arc, &last_pong, heartbeat) => {
+            log::error!("Plugin {} did not respond to ping in {:?}", plugin_id, heartbeat.timeout);
+
+            if let Some(plugin) = plugin_manager.lock().await.get_mut(&plugin_id) {
+                plugin.set_status(PluginStatus::Unresponsive);
+            }
+            let _ = sink_arc.lock().await.close().await;
+        }
+    }
+
+    log::info!("\t[plugin.server]: plugin session closed: {}", plugin_id);
+
+    if let Some(plugin) = plugin_manager.lock().await.get_mut(&plugin_id) {
+        plugin.session_closed(&sink_arc);
+    }
+
+    // セッションが終わった後に仮想デバイスからの入力が続かないようにする
+    ArdeckCore::close_virtual_devices_of(&plugin_id).await;
+}
+
+/// 定期的にpingを送り、最後のpongからtimeoutが過ぎたら戻る
+async fn keep_alive(
+    sink: &Arc<Mutex<PluginServerSink>>,
+    last_pong: &Arc<Mutex<Instant>>,
+    heartbeat: Heartbeat,
+) {
+    let mut ticker = tokio::time::interval(heartbeat.interval);
+
+    loop {
+        ticker.tick().await;
+
+        if last_pong.lock().await.elapsed() > heartbeat.timeout {
+            return;
+        }
+
+        // 送れない場合は受信側で切断が検出される
+        if let Err(e) = sink
+            .lock()
+            .await
+            .send(Message::Ping(Default::default()))
+            .await
+        {
+            log::debug!("Failed to send ping: {}", e);
+        }
+    }
+}
+
+async fn send_response(sink: &Arc<Mutex<PluginServerSink>>, response: &PluginMessage) {
+    let text = match serde_json::to_string(response) {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("Failed to serialize response: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = sink
+        .lock()
+        .await
+        .send(Message::Text(Utf8Bytes::from(text)))
+        .await
+    {
+        log::error!("Failed to send response to plugin: {}", e);
+    }
+}
+
+/// 接続を閉じる理由。codeと説明を、close frameの上限に収まるように切り詰める
+fn close_reason(e: &HandshakeError) -> Utf8Bytes {
+    const MAX_REASON_LEN: usize = 123;
+
+    let mut reason = format!("{}: {}", e.code(), e);
+    if reason.len() > MAX_REASON_LEN {
+        let mut end = MAX_REASON_LEN;
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        reason.truncate(end);
+    }
+
+    Utf8Bytes::from(reason)
+}
+
+/// Helloを待ち、plugin_idとトークンを確認してセッションを登録する
+async fn handshake(
+    stream: &mut PluginServerStream,
+    sink: &Arc<Mutex<PluginServerSink>>,
+    plugin_manager: &Arc<Mutex<PluginManager>>,
+) -> Result<String, HandshakeError> {
+    let msg_str = loop {
+        match stream.next().await {
+            Some(Ok(Message::Text(text))) => break text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                return Err(HandshakeError::Closed)
+            }
+            Some(Ok(_)) => continue,
+        }
+    };
+
+    let (plugin_id, plugin_version, web_socket_version, token) =
+        match serde_json::from_str(msg_str.as_str()) {
+            Ok(PluginMessage::Hello {
+                plugin_id,
+                plugin_version,
+                ardeck_plugin_web_socket_version,
+                token,
+            }) => (
+                plugin_id,
+                plugin_version,
+                ardeck_plugin_web_socket_version,
+                token,
+            ),
+            _ => return Err(HandshakeError::NotHello),
+        };
+
+    log::debug!(
+        "Hello:\n\t{} {} (protocol {})",
+        plugin_id,
+        plugin_version,
+        web_socket_version
+    );
+
+    let mut plugin_manager = plugin_manager.lock().await;
+    let plugin = plugin_manager
+        .get_mut(&plugin_id)
+        .ok_or_else(|| HandshakeError::UnknownPlugin(plugin_id.clone()))?;
+
+    // トークンを送らない古いプラグインには、認証の失敗ではなくバージョンの違いとして伝える
+    if token.is_empty() {
+        protocol::negotiate(&web_socket_version)?;
+    }
+
+    if !plugin.verify_token(&token) {
+        return Err(HandshakeError::InvalidToken(plugin_id));
     }
+
+    // 認証してからバージョンを確認する
+    let protocol_version = protocol::negotiate(&web_socket_version)?;
+
+    plugin.set_server_sink(sink.clone());
+
+    // 決まったプロトコルのバージョンをプラグインに伝える
+    plugin
+        .send_message(&PluginMessage::Success {
+            ardeck_studio_version: protocol::ARDECK_STUDIO_VERSION.to_string(),
+            ardeck_studio_web_socket_version: protocol_version.to_string(),
+        })
+        .await?;
+
+    log::info!(
+        "Plugin {} uses protocol {} (host: {})",
+        plugin_id,
+        protocol_version,
+        protocol::PROTOCOL_VERSION
+    );
+    plugin.protocol_version = Some(protocol_version);
+
+    plugin.set_status(PluginStatus::Connected);
+
+    // 接続するまでに発生したアクションを送る
+    plugin.flush_outbound().await;
+
+    Ok(plugin_id)
 }
@@ -0,0 +1,154 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde::{Deserialize, Serialize};
+
+use crate::ardeck_studio::switch_info::SwitchInfo;
+
+use super::supervisor::PluginStatus;
+
+/// プラグインが購読できるイベントの種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTopic {
+    /// デバイスのスイッチの状態の変化
+    Switch,
+    /// デバイスの接続と切断
+    Device,
+    /// デバイスのマッピングプリセットの変更
+    Preset,
+    /// プラグインの状態の変化
+    PluginStatus,
+}
+
+/// 購読の条件
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub topic: EventTopic,
+    /// 指定した場合は、そのデバイスのイベントだけを受け取る
+    /// デバイスに関係しないトピックでは無視する
+    #[serde(default)]
+    pub device_id: Option<String>,
+}
+
+impl Subscription {
+    pub fn matches(&self, event: &SubscribedEvent) -> bool {
+        if self.topic != event.topic() {
+            return false;
+        }
+
+        match (&self.device_id, event.device_id()) {
+            (Some(filter), Some(device_id)) => filter == device_id,
+            _ => true,
+        }
+    }
+}
+
+/// 購読しているプラグインへ送るイベント
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "topic", content = "event")]
+pub enum SubscribedEvent {
+    Switch {
+        device_id: String,
+        switch_info: SwitchInfo,
+    },
+    Device {
+        device_id: String,
+        port_name: String,
+        connected: bool,
+    },
+    Preset {
+        device_id: String,
+        preset_id: Option<String>,
+    },
+    PluginStatus {
+        plugin_id: String,
+        status: PluginStatus,
+    },
+}
+
+impl SubscribedEvent {
+    pub fn topic(&self) -> EventTopic {
+        match self {
+            Self::Switch { .. } => EventTopic::Switch,
+            Self::Device { .. } => EventTopic::Device,
+            Self::Preset { .. } => EventTopic::Preset,
+            Self::PluginStatus { .. } => EventTopic::PluginStatus,
+        }
+    }
+
+    pub fn device_id(&self) -> Option<&str> {
+        match self {
+            Self::Switch { device_id, .. }
+            | Self::Device { device_id, .. }
+            | Self::Preset { device_id, .. } => Some(device_id),
+            Self::PluginStatus { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(topic: EventTopic, device_id: Option<&str>) -> Subscription {
+        Subscription {
+            topic,
+            device_id: device_id.map(str::to_string),
+        }
+    }
+
+    fn device_event(device_id: &str) -> SubscribedEvent {
+        SubscribedEvent::Device {
+            device_id: device_id.to_string(),
+            port_name: "COM1".to_string(),
+            connected: true,
+        }
+    }
+
+    #[test]
+    fn matches_same_topic() {
+        assert!(subscription(EventTopic::Device, None).matches(&device_event("a")));
+        assert!(!subscription(EventTopic::Switch, None).matches(&device_event("a")));
+    }
+
+    #[test]
+    fn matches_filters_by_device_id() {
+        assert!(subscription(EventTopic::Device, Some("a")).matches(&device_event("a")));
+        assert!(!subscription(EventTopic::Device, Some("a")).matches(&device_event("b")));
+    }
+
+    #[test]
+    fn matches_ignores_device_id_for_plugin_status() {
+        let event = SubscribedEvent::PluginStatus {
+            plugin_id: "test".to_string(),
+            status: PluginStatus::Connected,
+        };
+
+        assert!(subscription(EventTopic::PluginStatus, Some("a")).matches(&event));
+    }
+
+    #[test]
+    fn subscription_deserializes_without_device_id() {
+        let subscription: Subscription =
+            serde_json::from_str(r#"{ "topic": "plugin_status" }"#).unwrap();
+
+        assert_eq!(subscription, self::subscription(EventTopic::PluginStatus, None));
+    }
+}
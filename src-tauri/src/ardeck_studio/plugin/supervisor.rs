@@ -0,0 +1,195 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task::JoinHandle, time::Instant};
+
+use super::{manager::PluginManager, Plugin};
+
+/// プロセスの状態を確認する間隔
+const TICK: Duration = Duration::from_millis(500);
+/// 最初の再起動までの待ち時間。クラッシュするたびに2倍になる
+const BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// CRASH_WINDOWの間にCRASH_LIMIT回を超えてクラッシュしたら、再起動を諦める
+const CRASH_LIMIT: usize = 5;
+const CRASH_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PluginStatus {
+    /// プロセスを起動し、Helloを待っている
+    Starting,
+    /// セッションが確立している
+    Connected,
+    /// セッションは確立しているが、pingに応答しない
+    Unresponsive,
+    /// プロセスが予期せず終了した
+    Crashed,
+    /// 終了を要求して止めた
+    Stopped,
+}
+
+/// クラッシュの履歴と、次に再起動する時刻
+#[derive(Debug, Default)]
+pub struct CrashHistory {
+    crashes: VecDeque<Instant>,
+    next_restart: Option<Instant>,
+}
+
+impl CrashHistory {
+    /// クラッシュを記録し、次に再起動する時刻を返す
+    /// 短時間にクラッシュを繰り返している場合はNoneを返す
+    pub fn record(&mut self, now: Instant) -> Option<Instant> {
+        while let Some(crashed_at) = self.crashes.front() {
+            if now.duration_since(*crashed_at) > CRASH_WINDOW {
+                self.crashes.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.crashes.push_back(now);
+
+        if self.crashes.len() > CRASH_LIMIT {
+            self.next_restart = None;
+            return None;
+        }
+
+        let backoff = BACKOFF_INITIAL
+            .saturating_mul(1 << (self.crashes.len() - 1))
+            .min(BACKOFF_MAX);
+        self.next_restart = Some(now + backoff);
+
+        self.next_restart
+    }
+
+    /// 再起動する予定があるか
+    pub fn will_restart(&self) -> bool {
+        self.next_restart.is_some()
+    }
+
+    /// 再起動する時刻になったか
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_restart.is_some_and(|next_restart| next_restart <= now)
+    }
+}
+
+/// プラグインのプロセスを監視し、クラッシュしたものを再起動する
+pub fn start(plugin_manager: Arc<Mutex<PluginManager>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(TICK).await;
+
+            let now = Instant::now();
+            for plugin in plugin_manager.lock().await.values_mut() {
+                supervise(plugin, now);
+            }
+        }
+    })
+}
+
+fn supervise(plugin: &mut Plugin, now: Instant) {
+    plugin.outbound.expire(now);
+
+    match plugin.status {
+        PluginStatus::Stopped => (),
+        PluginStatus::Crashed => {
+            if plugin.crash_history.is_due(now) {
+                log::info!("Restarting plugin: {}", plugin.manifest.id);
+                // 失敗した場合は再びCrashedになり、次の再起動を待つ
+                let _ = plugin.start();
+            }
+        }
+        PluginStatus::Unresponsive => {
+            log::error!("Plugin is not responding. Restarting: {}", plugin.manifest.id);
+            plugin.kill();
+            plugin.crashed(now);
+        }
+        PluginStatus::Starting | PluginStatus::Connected => {
+            plugin.expire_actions(now);
+
+            let exited = match plugin.process.as_mut().map(|process| process.try_wait()) {
+                Some(Ok(Some(status))) => Some(status.to_string()),
+                Some(Ok(None)) => None,
+                Some(Err(e)) => Some(e.to_string()),
+                None => Some("no process".to_string()),
+            };
+
+            if let Some(reason) = exited {
+                log::error!("Plugin exited unexpectedly: {} ({})", plugin.manifest.id, reason);
+                plugin.crashed(now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_doubles_backoff() {
+        let now = Instant::now();
+        let mut history = CrashHistory::default();
+
+        assert_eq!(history.record(now), Some(now + Duration::from_secs(1)));
+        assert_eq!(history.record(now), Some(now + Duration::from_secs(2)));
+        assert_eq!(history.record(now), Some(now + Duration::from_secs(4)));
+        assert!(history.will_restart());
+    }
+
+    #[test]
+    fn record_gives_up_after_limit() {
+        let now = Instant::now();
+        let mut history = CrashHistory::default();
+        for _ in 0..CRASH_LIMIT {
+            assert!(history.record(now).is_some());
+        }
+
+        assert_eq!(history.record(now), None);
+        assert!(!history.will_restart());
+        assert!(!history.is_due(now + BACKOFF_MAX));
+    }
+
+    #[test]
+    fn record_forgets_old_crashes() {
+        let now = Instant::now();
+        let mut history = CrashHistory::default();
+        for _ in 0..CRASH_LIMIT {
+            history.record(now);
+        }
+
+        // 窓の外になったクラッシュは数えず、待ち時間も最初に戻る
+        let later = now + CRASH_WINDOW + Duration::from_secs(1);
+        assert_eq!(history.record(later), Some(later + BACKOFF_INITIAL));
+    }
+
+    #[test]
+    fn is_due_after_backoff() {
+        let now = Instant::now();
+        let mut history = CrashHistory::default();
+        assert!(!history.is_due(now));
+
+        let next_restart = history.record(now).unwrap();
+        assert!(!history.is_due(now));
+        assert!(history.is_due(next_restart));
+    }
+}
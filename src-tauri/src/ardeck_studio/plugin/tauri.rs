@@ -16,59 +16,37 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use once_cell::sync::Lazy;
-use serialport::SerialPortInfo;
 use tauri::{
     generate_handler,
     plugin::{Builder, TauriPlugin},
-    RunEvent, Runtime,
+    Manager, Runtime,
 };
-use tokio::sync::Mutex;
+use std::path::PathBuf;
 
-use crate::{ardeck_studio::switch_info::SwitchInfo, service::dir::Directories};
+use serde_json::{Map, Value};
+use tokio::sync::broadcast::error::RecvError;
 
-use super::{server::PluginServer, PluginActionJSON, PluginManifestJSON};
+use crate::ardeck_studio::settings;
 
-static PLUGIN_SERVER: Lazy<Mutex<PluginServer>> = Lazy::new(|| Mutex::new(PluginServer::new()));
-
-async fn server_init() {
-    log::info!("Initializing plugin server...");
-
-    let mut server = PLUGIN_SERVER.lock().await;
-    // Directories::init(Directories::get_plugin_dir().unwrap()).unwrap();
-    let plugin_dir = match Directories::get_plugin_dir() {
-        Ok(dir) => dir,
-        Err(e) => {
-            log::error!("[init]  Failed to get plugin dir: {}", e);
-            return;
-        }
-    };
-
-    if let Err(e) = Directories::init(plugin_dir) {
-        log::error!("[init] Failed to init plugin dir: {}", e);
-        return;
-    };
-
-    match server.start().await {
-        Ok(_) => {
-            log::info!("Plugin server started.");
-            server.execute_plugin_all().await;
-        }
-        Err(e) => log::error!("Failed to start plugin server: {}", e),
-    };
-}
+use super::{
+    core::{self, server_init, PluginActionFailure, PluginEvent, PLUGIN_SERVER},
+    error::{InstallError, PluginError, PluginLoadError},
+    output::PluginOutputLine,
+    permission::PluginPermission,
+    PluginActionInfo, PluginInfo,
+};
 
 #[tauri::command]
 async fn get_plugin_manifests<R: Runtime>(
     _app: tauri::AppHandle<R>,
-) -> Result<Vec<PluginManifestJSON>, String> {
+) -> Result<Vec<PluginInfo>, PluginError> {
     let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
 
-    let manifests: Vec<PluginManifestJSON> = plugin_manager
+    let manifests: Vec<PluginInfo> = plugin_manager
         .lock()
         .await
-        .iter()
-        .map(|(_id, plugin)| plugin.manifest.clone())
+        .values()
+        .map(|plugin| plugin.info())
         .collect();
 
     Ok(manifests)
@@ -78,7 +56,7 @@ async fn get_plugin_manifests<R: Runtime>(
 async fn get_plugin_actions<R: Runtime>(
     app: tauri::AppHandle<R>,
     plugin_id: String,
-) -> Result<PluginActionJSON, String> {
+) -> Result<Vec<PluginActionInfo>, PluginError> {
     let plugin_server = PLUGIN_SERVER.lock().await;
 
     if let Some(plugin) = plugin_server
@@ -88,25 +66,156 @@ async fn get_plugin_actions<R: Runtime>(
         .await
         .get(&plugin_id)
     {
-        return Ok(plugin.actions.clone());
+        return Ok(plugin.action_infos());
     } else {
-        return Err("Plugin not found".to_string());
+        return Err(PluginError::NotFound(plugin_id));
     }
 }
 
+#[tauri::command]
+async fn set_plugin_enabled<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+    enabled: bool,
+) -> Result<PluginInfo, PluginError> {
+    core::set_plugin_enabled(&plugin_id, enabled).await
+}
+
+/// インストール時などに、ユーザーが確認した権限を保存する
+#[tauri::command]
+async fn set_plugin_permissions<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+    permissions: Vec<PluginPermission>,
+) -> Result<PluginInfo, PluginError> {
+    core::set_plugin_permissions(&plugin_id, permissions).await
+}
+
+#[tauri::command]
+async fn get_plugin_action_failures<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+) -> Result<Vec<PluginActionFailure>, PluginError> {
+    core::get_action_failures(&plugin_id).await
+}
+
+#[tauri::command]
+async fn get_plugin_output<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+) -> Result<Vec<PluginOutputLine>, PluginError> {
+    core::get_plugin_output(&plugin_id).await
+}
+
+#[tauri::command]
+async fn get_plugin_errors<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+) -> Result<Vec<PluginLoadError>, PluginError> {
+    Ok(core::get_plugin_errors().await)
+}
+
+#[tauri::command]
+async fn get_plugin_config<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+) -> Result<Map<String, Value>, PluginError> {
+    ensure_plugin_exists(&plugin_id).await?;
+
+    Ok(settings::core::get_plugin_config(&plugin_id).await?)
+}
+
+#[tauri::command]
+async fn set_plugin_config<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+    config: Map<String, Value>,
+) -> Result<(), PluginError> {
+    ensure_plugin_exists(&plugin_id).await?;
+
+    Ok(core::set_plugin_config(&plugin_id, config, true).await?)
+}
+
+#[tauri::command]
+async fn install_plugin<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    archive_path: String,
+) -> Result<PluginInfo, InstallError> {
+    core::install_plugin(PathBuf::from(archive_path)).await
+}
+
+#[tauri::command]
+async fn uninstall_plugin<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    plugin_id: String,
+) -> Result<(), InstallError> {
+    core::uninstall_plugin(&plugin_id).await
+}
+
+async fn ensure_plugin_exists(plugin_id: &str) -> Result<(), PluginError> {
+    let plugin_manager = PLUGIN_SERVER.lock().await.get_plugin_manager().await;
+
+    if plugin_manager.lock().await.contains_key(plugin_id) {
+        Ok(())
+    } else {
+        Err(PluginError::NotFound(plugin_id.to_string()))
+    }
+}
+
+// プラグインサーバーのイベントをフロントエンドへ転送する
+fn forward_events<R: Runtime>(tauri_app: tauri::AppHandle<R>) {
+    let mut events = core::subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let result = match events.recv().await {
+                Ok(PluginEvent::Status(payload)) => tauri_app.emit_all("on-plugin-status", payload),
+                Ok(PluginEvent::Notification(payload)) => {
+                    tauri_app.emit_all("on-plugin-notification", payload)
+                }
+                Ok(PluginEvent::ConfigChanged(payload)) => {
+                    tauri_app.emit_all("on-plugin-config", payload)
+                }
+                Ok(PluginEvent::ListChanged) => tauri_app.emit_all("on-plugin-list", ()),
+                Ok(PluginEvent::ActionFailed(payload)) => {
+                    tauri_app.emit_all("on-plugin-action-failed", payload)
+                }
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Plugin event lagged: {} events skipped", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if let Err(e) = result {
+                log::error!("Failed to emit plugin event: {}", e);
+            }
+        }
+    });
+}
+
 pub async fn init<R: Runtime>() -> TauriPlugin<R> {
     Builder::new("ardeck-plugin")
         .setup(|app| {
+            forward_events(app.app_handle());
+
             tokio::spawn(async {
                 server_init().await;
             });
 
             Ok(())
         })
-        .invoke_handler(generate_handler![get_plugin_manifests, get_plugin_actions])
+        .invoke_handler(generate_handler![
+            get_plugin_manifests,
+            get_plugin_actions,
+            get_plugin_errors,
+            set_plugin_enabled,
+            set_plugin_permissions,
+            get_plugin_action_failures,
+            get_plugin_output,
+            get_plugin_config,
+            set_plugin_config,
+            install_plugin,
+            uninstall_plugin
+        ])
         .build()
 }
-
-pub async fn send_action_to_plugins(port_info: SerialPortInfo, data: SwitchInfo) {
-    PLUGIN_SERVER.lock().await.put_action(port_info, data.clone()).await;
-}
@@ -0,0 +1,96 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use super::core;
+
+/// 最後の変更からこの時間だけ変更がなければ再読み込みする
+/// ビルド中の書き込みが終わるのを待つため
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// プラグインディレクトリを監視し、変更があったプラグインを再読み込みする
+/// 返されたWatcherが破棄されると監視は止まる
+pub fn start(plugin_dir: PathBuf) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = mpsc::unbounded_channel::<PathBuf>();
+
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+        match result {
+            Ok(event) => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            Err(e) => log::warn!("Plugin watch error: {}", e),
+        }
+    })?;
+    watcher.watch(&plugin_dir, RecursiveMode::Recursive)?;
+
+    log::info!("Watching plugin dir: {}", plugin_dir.display());
+
+    tokio::spawn(debounce(plugin_dir, rx));
+
+    Ok(watcher)
+}
+
+async fn debounce(plugin_dir: PathBuf, mut rx: UnboundedReceiver<PathBuf>) {
+    while let Some(path) = rx.recv().await {
+        let mut changed: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        add_changed(&plugin_dir, &mut changed, path);
+
+        // 変更が落ち着くまで待つ
+        loop {
+            match tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                Ok(Some(path)) => add_changed(&plugin_dir, &mut changed, path),
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        for (dir, paths) in changed {
+            core::reload_plugin_dir(&dir, &paths).await;
+        }
+    }
+}
+
+/// 変更されたパスを、それを含むプラグインのディレクトリごとにまとめる
+fn add_changed(plugin_dir: &Path, changed: &mut HashMap<PathBuf, Vec<PathBuf>>, path: PathBuf) {
+    let name = match path
+        .strip_prefix(plugin_dir)
+        .ok()
+        .and_then(|relative| relative.components().next())
+    {
+        Some(Component::Normal(name)) => name.to_owned(),
+        _ => return,
+    };
+
+    // インストール中の一時ディレクトリなどは無視する
+    if name.to_string_lossy().starts_with('.') {
+        return;
+    }
+
+    changed.entry(plugin_dir.join(name)).or_default().push(path);
+}
@@ -18,11 +18,11 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
     fmt::Debug,
-    io,
     path::{Path, PathBuf},
 };
 
 use cache::Cache;
+use error::SettingsError;
 use once_cell::sync::Lazy;
 use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
@@ -32,15 +32,11 @@ use tokio::{
 };
 
 pub mod cache;
+pub mod core;
 pub mod definitions;
+pub mod error;
 pub mod tauri;
 
-#[derive(Debug)]
-pub enum SettingsStoreError {
-    IoError(io::Error),
-    SerdeError(serde_json::Error),
-}
-
 pub trait SettingFile: Serialize + DeserializeOwned + Default + Clone + Send + Sync {
     fn name(&self) -> &'static str;
     fn dir(&self) -> PathBuf;
@@ -70,32 +66,40 @@ pub trait SettingsStore:
 
     /// キャッシュを無視してファイルから直接読み込む。
     /// ここで読み込まれたデータもキャッシュに保存される。
-    async fn load_force(&mut self) -> Option<Self> {
+    async fn load_force(&mut self) -> Result<Self, SettingsError> {
         let file_path = self.file_path();
-        let mut file = self.file_open(&file_path).await;
+        let file = self.file_open(&file_path).await;
 
         // ファイルが存在しない場合は空のデータをセーブする。
         if file.is_none() {
-            self.save().await;
+            self.save().await?;
 
-            return Some(Self::default());
+            return Ok(Self::default());
         }
 
         let mut reader = BufReader::new(file.unwrap());
 
         let mut file_str = String::new();
 
-        reader.read_to_string(&mut file_str).await.unwrap();
-        Some(serde_json::from_str(&file_str).unwrap())
+        reader
+            .read_to_string(&mut file_str)
+            .await
+            .map_err(|source| SettingsError::Io {
+                path: file_path.clone(),
+                source,
+            })?;
+
+        serde_json::from_str(&file_str).map_err(|source| SettingsError::Serde {
+            path: file_path,
+            source,
+        })
     }
 
     /// ファイルを読み込みます。
     /// キャッシュが存在する場合はキャッシュをそのまま返し、キャッシュがファイルより古い場合はファイルを読み込みます。
-    async fn load(&mut self) -> Option<Self> {
-        // TODO: cache
-        // self.inner.iter().find(|a| a.0.)
+    async fn load(&mut self) -> Result<Self, SettingsError> {
         let file_path = self.file_path();
-        let mut file: Option<File> = None;
+        let file: Option<File>;
         let mut file_str = String::new();
 
         if CACHE.lock().await.get(&file_path).is_none() {
@@ -107,12 +111,18 @@ pub trait SettingsStore:
 
             // ファイルが存在しない場合は空のデータをセーブする。
             if file.is_none() {
-                self.save().await;
+                self.save().await?;
 
-                return Some(Self::default());
+                return Ok(Self::default());
             }
 
-            file.unwrap().read_to_string(&mut file_str).await.unwrap();
+            file.unwrap()
+                .read_to_string(&mut file_str)
+                .await
+                .map_err(|source| SettingsError::Io {
+                    path: file_path.clone(),
+                    source,
+                })?;
 
             // キャッシュを作る
             CACHE
@@ -126,10 +136,16 @@ pub trait SettingsStore:
             file = self.file_open(&file_path).await;
 
             if file.is_none() {
-                return Some(Self::default());
+                return Ok(Self::default());
             }
 
-            file.unwrap().read_to_string(&mut file_str).await.unwrap();
+            file.unwrap()
+                .read_to_string(&mut file_str)
+                .await
+                .map_err(|source| SettingsError::Io {
+                    path: file_path.clone(),
+                    source,
+                })?;
 
             // キャッシュを更新する
             CACHE.lock().await.update_data(&file_path, file_str.clone());
@@ -138,28 +154,35 @@ pub trait SettingsStore:
             log::debug!("load(from cache): {}", file_path.display());
 
             let cache = CACHE.lock().await.get_data(&file_path);
-            file_str = cache.unwrap();
+            file_str = cache.unwrap_or_default();
         };
 
-        match serde_json::from_str(&file_str) {
-            Ok(setting) => {
-                self.clone_from(&setting);
-                return Some(setting);
-            }
-            Err(_) => return None,
-        }
+        let setting: Self = serde_json::from_str(&file_str).map_err(|source| SettingsError::Serde {
+            path: file_path,
+            source,
+        })?;
+
+        self.clone_from(&setting);
+        Ok(setting)
     }
 
-    async fn save(&self) {
-        // TODO: Error handling
+    async fn save(&self) -> Result<(), SettingsError> {
         let path = self.file_path();
         log::info!("File save: {}", path.display());
 
-        let file = std::fs::File::create(&path).unwrap();
+        let file = std::fs::File::create(&path).map_err(|source| SettingsError::Io {
+            path: path.clone(),
+            source,
+        })?;
         let writer = std::io::BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, self).unwrap();
+        serde_json::to_writer_pretty(writer, self).map_err(|source| SettingsError::Serde {
+            path: path.clone(),
+            source,
+        })?;
 
         CACHE.lock().await.mark_dirty(&path);
+
+        Ok(())
     }
 }
 
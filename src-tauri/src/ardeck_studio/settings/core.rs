@@ -0,0 +1,233 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use serde_json::{Map, Value};
+
+use crate::{
+    ardeck_studio::plugin::{
+        self, permission::PluginPermission, subscription::SubscribedEvent,
+    },
+    service::dir::Directories,
+};
+
+use super::{
+    definitions::{
+        ardeck::{ArdeckProfileConfigItem, ArdeckProfileConfigJSON},
+        ardeck_studio::{ArdeckStudioConfigItem, ArdeckStudioConfigJSON},
+        mapping_presets::MappingPresetsJSON,
+        plugin::PluginConfigJSON,
+    },
+    error::SettingsError,
+    SettingFile, SettingsStore, CACHE,
+};
+
+/// 設定ディレクトリを初期化する
+pub fn init() -> std::io::Result<()> {
+    Directories::init(Directories::get_settings_dir()?)?;
+    Directories::init(Directories::get_plugin_settings_dir()?)
+}
+
+/// アプリケーション全体の設定を取得する
+pub async fn get_studio_config() -> Result<ArdeckStudioConfigItem, SettingsError> {
+    ArdeckStudioConfigJSON::default().load().await
+}
+
+/// プラグインが有効か
+/// 設定を読み込めない場合は有効として扱う
+pub async fn is_plugin_enabled(plugin_id: &str) -> bool {
+    match get_studio_config().await {
+        Ok(config) => !config.disabled_plugins.iter().any(|id| id == plugin_id),
+        Err(e) => {
+            log::error!("Failed to load studio config: {}", e);
+            true
+        }
+    }
+}
+
+/// プラグインの有効、無効を保存する
+pub async fn set_plugin_enabled(plugin_id: &str, enabled: bool) -> Result<(), SettingsError> {
+    let mut config = get_studio_config().await?;
+
+    config.disabled_plugins.retain(|id| id != plugin_id);
+    if !enabled {
+        config.disabled_plugins.push(plugin_id.to_string());
+    }
+
+    config.save().await
+}
+
+/// ユーザーが許可したプラグインの権限を取得する
+/// 設定を読み込めない場合は何も許可しない
+pub async fn get_granted_permissions(plugin_id: &str) -> Vec<PluginPermission> {
+    match get_studio_config().await {
+        Ok(mut config) => config
+            .granted_permissions
+            .remove(plugin_id)
+            .unwrap_or_default(),
+        Err(e) => {
+            log::error!("Failed to load studio config: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// ユーザーに確認したプラグインの権限を取得する
+/// 許可しなかったものも含む
+pub async fn get_reviewed_permissions(plugin_id: &str) -> Vec<PluginPermission> {
+    match get_studio_config().await {
+        Ok(mut config) => config
+            .reviewed_permissions
+            .remove(plugin_id)
+            .unwrap_or_default(),
+        Err(e) => {
+            log::error!("Failed to load studio config: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// ユーザーが許可したプラグインの権限と、確認した権限を保存する
+/// 空の場合は記録を削除する
+pub async fn set_granted_permissions(
+    plugin_id: &str,
+    granted: Vec<PluginPermission>,
+    reviewed: Vec<PluginPermission>,
+) -> Result<(), SettingsError> {
+    let mut config = get_studio_config().await?;
+
+    if granted.is_empty() {
+        config.granted_permissions.remove(plugin_id);
+    } else {
+        config
+            .granted_permissions
+            .insert(plugin_id.to_string(), granted);
+    }
+
+    if reviewed.is_empty() {
+        config.reviewed_permissions.remove(plugin_id);
+    } else {
+        config
+            .reviewed_permissions
+            .insert(plugin_id.to_string(), reviewed);
+    }
+
+    config.save().await
+}
+
+/// プラグインの設定を取得する
+pub async fn get_plugin_config(plugin_id: &str) -> Result<Map<String, Value>, SettingsError> {
+    let config = PluginConfigJSON::new(plugin_id).load().await?;
+
+    Ok(config.values)
+}
+
+/// プラグインの設定を置き換えて保存する
+pub async fn set_plugin_config(
+    plugin_id: &str,
+    values: Map<String, Value>,
+) -> Result<(), SettingsError> {
+    let mut config = PluginConfigJSON::new(plugin_id);
+    config.values = values;
+
+    config.save().await
+}
+
+/// プラグインの設定ファイルとキャッシュを削除する
+/// ファイルが無い場合は何もしない
+pub async fn remove_plugin_config(plugin_id: &str) -> Result<(), SettingsError> {
+    let path = PluginConfigJSON::new(plugin_id).file_path();
+    log::info!("File delete: {}", path.display());
+
+    match std::fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(source) => return Err(SettingsError::Io { path, source }),
+    }
+
+    CACHE.lock().await.remove(&path);
+
+    Ok(())
+}
+
+/// device_idに対応するデバイスのプロファイルを取得する
+pub async fn get_ardeck_profile(
+    device_id: &str,
+) -> Result<Option<ArdeckProfileConfigItem>, SettingsError> {
+    let config = ArdeckProfileConfigJSON::new().load().await?;
+
+    for item in config.iter() {
+        if item.device_id == device_id {
+            return Ok(Some(item.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// マッピングプリセットの(uuid, 表示名)の一覧を取得する
+pub async fn get_mapping_list() -> Result<Vec<(String, String)>, SettingsError> {
+    let mapping_presets = MappingPresetsJSON::new().load().await?;
+
+    Ok(mapping_presets
+        .iter()
+        .map(|a| (a.uuid.clone(), a.preset_name.clone()))
+        .collect())
+}
+
+/// デバイスで使うマッピングプリセットを切り替える
+/// プロファイルが存在しない場合は新規に作成する
+pub async fn set_mapping_preset(
+    device_id: &str,
+    preset_id: &str,
+) -> Result<ArdeckProfileConfigItem, SettingsError> {
+    let presets = get_mapping_list().await?;
+    if !presets.iter().any(|(uuid, _)| uuid == preset_id) {
+        return Err(SettingsError::PresetNotFound(preset_id.to_string()));
+    }
+
+    let mut config = ArdeckProfileConfigJSON::new().load().await?;
+
+    let profile = match config.iter().position(|p| p.device_id == device_id) {
+        Some(i) => {
+            config[i].mapping_preset = Some(preset_id.to_string());
+            config[i].clone()
+        }
+        None => {
+            let profile = ArdeckProfileConfigItem {
+                device_id: device_id.to_string(),
+                device_name: None,
+                baud_rate: None,
+                description: None,
+                mapping_preset: Some(preset_id.to_string()),
+            };
+            config.push(profile.clone());
+            profile
+        }
+    };
+
+    config.save().await?;
+
+    log::info!("Mapping preset changed: {} -> {}", device_id, preset_id);
+    plugin::core::publish_event(SubscribedEvent::Preset {
+        device_id: device_id.to_string(),
+        preset_id: Some(preset_id.to_string()),
+    });
+
+    Ok(profile)
+}
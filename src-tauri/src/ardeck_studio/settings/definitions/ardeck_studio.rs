@@ -4,23 +4,114 @@ Copyright (C) 2024 Project Ardeck
 
 This program is free software; you can redistribute it and/or modify
 it under the terms of the GNU General Public License as published by
-the Free Software Foundation; either version 3 of the License, or 
+the Free Software Foundation; either version 3 of the License, or
 (at your option) any later version.
 
-This program is distributed in the hope that it will be useful, 
+This program is distributed in the hope that it will be useful,
 but WITHOUT ANY WARRANTY; without even the implied warranty of
-MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the 
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
 GNU General Public License for more details.
 
 You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
 use serde::{Deserialize, Serialize};
 use struct_field_names_as_array::FieldNamesAsArray;
 
+use crate::{
+    ardeck_studio::{
+        plugin::permission::PluginPermission,
+        settings::{SettingFile, SettingsStore},
+    },
+    service::dir::Directories,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, FieldNamesAsArray)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArdeckStudioConfigItem {
+    /// プラグインサーバーの設定
+    pub plugin_server: PluginServerConfig,
+    /// 無効にしたプラグインのID
+    /// 新しくインストールしたプラグインは有効にしたいため、無効なものを記録する
+    pub disabled_plugins: Vec<String>,
+    /// プラグインのmainを実行するインタプリタ
+    pub runtimes: RuntimeConfig,
+    /// プラグインIDごとの、ユーザーが許可した権限
+    pub granted_permissions: HashMap<String, Vec<PluginPermission>>,
+    /// プラグインIDごとの、ユーザーに確認した権限。許可しなかったものも含む
+    pub reviewed_permissions: HashMap<String, Vec<PluginPermission>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FieldNamesAsArray)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PluginServerConfig {
+    /// 待ち受けるアドレス
+    pub host: String,
+    /// 待ち受けるポート番号。0ならOSが割り当てる
+    pub port: u16,
+    /// portが使えない場合に、OSが割り当てたポートで待ち受けるか
+    pub port_fallback: bool,
+    /// セッションごとにpingを送る間隔(秒)
+    pub ping_interval_secs: u64,
+    /// pongが届かなくなってから応答なしとみなすまでの時間(秒)
+    pub pong_timeout_secs: u64,
+}
+
+impl Default for PluginServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 6725,
+            port_fallback: true,
+            ping_interval_secs: 10,
+            pong_timeout_secs: 30,
+        }
+    }
+}
+
+impl PluginServerConfig {
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_secs(self.ping_interval_secs.max(1))
+    }
+
+    pub fn pong_timeout(&self) -> Duration {
+        Duration::from_secs(self.pong_timeout_secs.max(1))
+    }
+}
+
+/// インタプリタのコマンド名かパス
 #[derive(Debug, Serialize, Deserialize, Clone, FieldNamesAsArray)]
-#[serde(rename_all = "camelCase")]
-pub struct ArdeckStudioConfigItem {}
+#[serde(rename_all = "camelCase", default)]
+pub struct RuntimeConfig {
+    pub node: String,
+    pub bun: String,
+    pub python: String,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            node: "node".to_string(),
+            bun: "bun".to_string(),
+            python: if cfg!(windows) { "python" } else { "python3" }.to_string(),
+        }
+    }
+}
 
 pub type ArdeckStudioConfigJSON = ArdeckStudioConfigItem;
+
+impl SettingFile for ArdeckStudioConfigJSON {
+    fn name(&self) -> &'static str {
+        "ardeck_studio"
+    }
+
+    fn dir(&self) -> PathBuf {
+        // TODO: Log
+        Directories::get_settings_dir().unwrap()
+    }
+}
+
+impl SettingsStore for ArdeckStudioConfigJSON {}
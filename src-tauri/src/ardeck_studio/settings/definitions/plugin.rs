@@ -4,23 +4,75 @@ Copyright (C) 2024 Project Ardeck
 
 This program is free software; you can redistribute it and/or modify
 it under the terms of the GNU General Public License as published by
-the Free Software Foundation; either version 3 of the License, or 
+the Free Software Foundation; either version 3 of the License, or
 (at your option) any later version.
 
-This program is distributed in the hope that it will be useful, 
+This program is distributed in the hope that it will be useful,
 but WITHOUT ANY WARRANTY; without even the implied warranty of
-MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the 
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
 GNU General Public License for more details.
 
 You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use struct_field_names_as_array::FieldNamesAsArray;
 
-#[derive(Debug, Serialize, Deserialize, Clone, FieldNamesAsArray)]
-#[serde(rename_all = "camelCase")]
-pub struct PluginConfigItem {}
+use crate::{
+    ardeck_studio::settings::{SettingFile, SettingsStore},
+    service::dir::Directories,
+};
+
+/// プラグインごとの設定
+/// 中身はプラグインが自由に決める
+#[derive(Debug, Serialize, Deserialize, Clone, Default, FieldNamesAsArray)]
+pub struct PluginConfigItem {
+    /// ファイル名に使う。ファイルには保存しない
+    #[serde(skip)]
+    plugin_id: String,
+    #[serde(flatten)]
+    pub values: Map<String, Value>,
+}
+
+impl PluginConfigItem {
+    pub fn new(plugin_id: &str) -> Self {
+        Self {
+            plugin_id: plugin_id.to_string(),
+            values: Map::new(),
+        }
+    }
+}
 
 pub type PluginConfigJSON = PluginConfigItem;
+
+impl SettingFile for PluginConfigJSON {
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn dir(&self) -> PathBuf {
+        // TODO: Log
+        Directories::get_plugin_settings_dir().unwrap()
+    }
+
+    fn file_path(&self) -> PathBuf {
+        // プラグインIDをそのままパスに使わないよう、使える文字以外は置き換える
+        let file_name: String = self
+            .plugin_id
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+
+        self.dir().join(format!("{}.json", file_name))
+    }
+}
+
+impl SettingsStore for PluginConfigJSON {}
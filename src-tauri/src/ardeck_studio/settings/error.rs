@@ -0,0 +1,71 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::{io, path::PathBuf};
+
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::ardeck_studio::{
+    error::{serialize_error, ErrorCode},
+    plugin::error::ParameterError,
+};
+
+/// 設定ファイルの読み書きのエラー
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("Failed to access settings file: {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to parse settings file: {}", path.display())]
+    Serde {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Mapping preset not found: {0}")]
+    PresetNotFound(String),
+    #[error("Invalid parameters for {plugin_id}/{action_id}: {source}")]
+    InvalidParameters {
+        plugin_id: String,
+        action_id: String,
+        #[source]
+        source: ParameterError,
+    },
+}
+
+impl ErrorCode for SettingsError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Io { .. } => "settings.io",
+            Self::Serde { .. } => "settings.parse",
+            Self::PresetNotFound(_) => "settings.preset_not_found",
+            Self::InvalidParameters { .. } => "settings.invalid_parameters",
+        }
+    }
+}
+
+impl Serialize for SettingsError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
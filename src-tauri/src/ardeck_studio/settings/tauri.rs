@@ -28,14 +28,15 @@ use uuid::Uuid;
 use crate::{
     ardeck_studio::{
         action::action_map::ActionMap,
+        plugin::{self, core::validate_action_parameters, subscription::SubscribedEvent},
         settings::definitions::{ardeck::ArdeckProfileConfigItem, mapping_presets::MappingPreset},
         switch_info::SwitchType,
     },
-    service::dir::Directories,
 };
 
 use super::{
     definitions::{ardeck::ArdeckProfileConfigJSON, mapping_presets::MappingPresetsJSON},
+    error::SettingsError,
     SettingsStore,
 };
 
@@ -44,8 +45,8 @@ use super::{
 async fn get_mapping_list<R: Runtime>(
     app: tauri::AppHandle<R>,
     // mapping_presets_json: State<'_, Mutex<MappingPresetsJSON>>,
-) -> Result<Vec<(String, String)>, String> {
-    let mapping_presets = MappingPresetsJSON::new().load_force().await.unwrap();
+) -> Result<Vec<(String, String)>, SettingsError> {
+    let mapping_presets = MappingPresetsJSON::new().load_force().await?;
     let list: Vec<(String, String)> = mapping_presets
         .iter()
         .map(|a| (a.uuid.clone(), a.preset_name.clone()))
@@ -62,12 +63,12 @@ async fn get_mapping_preset<R: Runtime>(
     app: tauri::AppHandle<R>,
     // mapping_presets_json: State<'_, Mutex<MappingPresetsJSON>>,
     uuid: &str,
-) -> Result<Option<MappingPreset>, String> {
+) -> Result<Option<MappingPreset>, SettingsError> {
     log::debug!("get_mapping_preset: {}", uuid);
 
     log::debug!("get_mapping_preset.uuid: {}", uuid);
 
-    let mapping_presets = MappingPresetsJSON::new().load().await.unwrap();
+    let mapping_presets = MappingPresetsJSON::new().load().await?;
     for a in mapping_presets.iter() {
         log::debug!("\tuuid: {}", a.uuid);
 
@@ -86,12 +87,24 @@ async fn save_mapping_preset<R: Runtime>(
     app: tauri::AppHandle<R>,
     // mapping_presets_json: State<'_, Mutex<MappingPresetsJSON>>,
     mut mapping_preset: MappingPreset,
-) -> Result<MappingPreset, String> {
-    let mut mapping_presets = MappingPresetsJSON::new().load().await.unwrap();
+) -> Result<MappingPreset, SettingsError> {
+    let mut mapping_presets = MappingPresetsJSON::new().load().await?;
     log::debug!("save_mapping_preset: {:#?}", mapping_preset);
 
     log::debug!("save_mapping_preset.uuid: {}", mapping_preset.uuid);
 
+    // パラメーターを検証し、省略された値をデフォルト値で埋める
+    for map in mapping_preset.mapping.iter_mut() {
+        map.parameters =
+            validate_action_parameters(&map.plugin_id, &map.action_id, &map.parameters)
+                .await
+                .map_err(|source| SettingsError::InvalidParameters {
+                    plugin_id: map.plugin_id.clone(),
+                    action_id: map.action_id.clone(),
+                    source,
+                })?;
+    }
+
     // すでに存在するかを確認する
     let index = mapping_presets
         .iter()
@@ -104,7 +117,7 @@ async fn save_mapping_preset<R: Runtime>(
             log::debug!("save_mapping_preset.data_change");
             log::debug!("mapping_presets[after]: {:#?}", mapping_presets);
 
-            mapping_presets.save().await;
+            mapping_presets.save().await?;
         }
         // 存在しなければ、新規追加する
         None => {
@@ -116,7 +129,7 @@ async fn save_mapping_preset<R: Runtime>(
             log::debug!("save_mapping_preset.new_data");
             log::debug!("mapping_presets[after]: {:#?}", mapping_presets);
 
-            mapping_presets.save().await;
+            mapping_presets.save().await?;
         }
     }
 
@@ -130,16 +143,10 @@ type ProfileList = Vec<(DeviceId, DeviceName)>;
 #[tauri::command]
 async fn get_ardeck_profile_list<R: Runtime>(
     app: tauri::AppHandle<R>,
-) -> Result<ProfileList, String> {
-    let config = ArdeckProfileConfigJSON::new().load().await;
+) -> Result<ProfileList, SettingsError> {
+    let config = ArdeckProfileConfigJSON::new().load().await?;
     let mut list: ProfileList = Vec::new();
 
-    if config.is_none() {
-        return Err("Failed to load ardeck profile config".into());
-    }
-
-    let config = config.unwrap();
-
     for item in config.iter() {
         list.push((item.device_id.clone(), item.device_name.clone()));
     }
@@ -147,52 +154,43 @@ async fn get_ardeck_profile_list<R: Runtime>(
     Ok(list)
 }
 
-pub async fn _get_ardeck_profile(
-    device_id: &str,
-) -> Result<Option<ArdeckProfileConfigItem>, String> {
-    let config = ArdeckProfileConfigJSON::new().load().await;
-    if config.is_none() {
-        return Err("Failed to load ardeck profile config".into());
-    }
-
-    let config = config.unwrap();
-
-    for item in config.iter() {
-        if item.device_id == device_id {
-            return Ok(Some(item.clone()));
-        }
-    }
-
-    Ok(None)
-}
-
 #[tauri::command]
 async fn get_ardeck_profile<R: Runtime>(
     app: tauri::AppHandle<R>,
     device_id: &str,
-) -> Result<Option<ArdeckProfileConfigItem>, String> {
-    _get_ardeck_profile(device_id).await
+) -> Result<Option<ArdeckProfileConfigItem>, SettingsError> {
+    super::core::get_ardeck_profile(device_id).await
 }
 
 #[tauri::command]
 async fn save_ardeck_profile<R: Runtime>(
     app: tauri::AppHandle<R>,
     profile: ArdeckProfileConfigItem,
-) -> Result<ArdeckProfileConfigItem, String> {
-    let mut config = ArdeckProfileConfigJSON::new().load().await.unwrap();
+) -> Result<ArdeckProfileConfigItem, SettingsError> {
+    let mut config = ArdeckProfileConfigJSON::new().load().await?;
 
     let position = config.iter().position(|p| p.device_id == profile.device_id);
 
-    match position {
+    let prev_preset = match position {
         Some(i) => {
+            let prev_preset = config[i].mapping_preset.clone();
             config[i] = profile.clone();
+            prev_preset
         }
         None => {
             config.push(profile.clone());
+            None
         }
-    }
+    };
 
-    config.save().await;
+    config.save().await?;
+
+    if prev_preset != profile.mapping_preset {
+        plugin::core::publish_event(SubscribedEvent::Preset {
+            device_id: profile.device_id.clone(),
+            preset_id: profile.mapping_preset.clone(),
+        });
+    }
 
     Ok(profile)
 }
@@ -205,7 +203,7 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
     Builder::new("settings")
         .setup(|app| {
             // TODO: get_config_dir() log
-            Directories::init(Directories::get_settings_dir().unwrap()).unwrap();
+            super::core::init()?;
 
             Ok(())
         })
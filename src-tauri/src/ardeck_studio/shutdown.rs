@@ -0,0 +1,73 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+use std::time::Duration;
+
+use tokio::sync::OnceCell;
+
+use super::{ardeck::core::ArdeckCore, control, plugin};
+
+/// デバイスの読み取りが止まるのを待つ時間
+const READER_TIMEOUT: Duration = Duration::from_secs(2);
+/// プラグインが自分で終了するのを待つ時間。過ぎたらkillする
+const PLUGIN_TIMEOUT: Duration = Duration::from_secs(3);
+
+static SHUTDOWN: OnceCell<()> = OnceCell::const_new();
+
+/// 終了処理を行う
+/// 複数の経路から同時に呼ばれても、終了処理は1回だけ行われ、全員がその完了を待つ
+pub async fn shutdown() {
+    SHUTDOWN
+        .get_or_init(|| async {
+            log::info!("Shutting down...");
+
+            ArdeckCore::close_all(READER_TIMEOUT).await;
+            plugin::core::stop_plugin_all(PLUGIN_TIMEOUT).await;
+            control::remove_token();
+
+            log::info!("Shutdown completed.");
+            log::logger().flush();
+        })
+        .await;
+}
+
+/// SIGTERMまたはCtrl+Cを受け取るまで待つ
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => log::info!("SIGTERM received."),
+                    _ = tokio::signal::ctrl_c() => log::info!("Interrupted."),
+                }
+                return;
+            }
+            Err(e) => log::error!("Failed to listen SIGTERM: {}", e),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen Ctrl+C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    log::info!("Interrupted.");
+}
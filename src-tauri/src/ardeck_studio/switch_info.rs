@@ -347,6 +347,11 @@ impl ActionDataParser {
         self.compare.on_change_action(callback);
     }
 
+    /// スイッチごとの最新の状態を取得する
+    pub fn switch_states(&self) -> Vec<SwitchInfo> {
+        self.compare.states()
+    }
+
     fn on_complete_emit_all(&mut self, action: SwitchInfo) {
         self.countup_complete();
         self.compare.put_action(action.clone()); // on change actionのために
@@ -63,6 +63,14 @@ impl ActionCompare {
 
     }
 
+    /// スイッチごとの最新の状態をスイッチID順に返す
+    pub fn states(&self) -> Vec<SwitchInfo> {
+        let mut states: Vec<SwitchInfo> = self.prev_actions.values().cloned().collect();
+        states.sort_by_key(|s| s.get_switch_id());
+
+        states
+    }
+
     pub fn on_change_action<F: Fn(SwitchInfo) + Send + 'static>(&mut self, callback: F) {
         self.on_change_action.push(Box::new(callback));
     }
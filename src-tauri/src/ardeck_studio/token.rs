@@ -0,0 +1,47 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+/// 2つのトークンが一致するか
+/// 比較にかかる時間から推測されないよう、長さが同じなら全てのバイトを比較する
+pub fn verify(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_accepts_same_token() {
+        assert!(verify("0123abcd", "0123abcd"));
+        assert!(verify("", ""));
+    }
+
+    #[test]
+    fn verify_rejects_other_token() {
+        for token in ["0123abce", "0123abc", "0123abcde", ""] {
+            assert!(!verify("0123abcd", token), "{}", token);
+        }
+    }
+}
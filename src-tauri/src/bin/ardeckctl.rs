@@ -0,0 +1,186 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+//! ardeckctl - 起動中のardeck studioをコマンドラインから操作する
+
+use std::process::ExitCode;
+
+use ardeck::ardeck_studio::{
+    ardeck::core::ArdeckCore,
+    control::{ControlClient, ControlRequest, ControlResponse, ControlSwitchEvent},
+};
+
+const USAGE: &str = "\
+Usage: ardeckctl <command> [args]
+
+Commands:
+    ports                             List serial ports with their device ids
+    open <port_name> [baud_rate]      Open a device on the running studio
+    close <port_name>                 Close a device on the running studio
+    events [--json]                   Stream decoded switch events
+    presets                           List mapping presets
+    set-preset <device_id> <preset>   Switch the mapping preset of a device";
+
+const DEFAULT_BAUD_RATE: u32 = 9600;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    let result = match args.as_slice() {
+        ["ports"] => ports().await,
+        ["open", port_name] => open(port_name, DEFAULT_BAUD_RATE).await,
+        ["open", port_name, baud_rate] => match baud_rate.parse() {
+            Ok(baud_rate) => open(port_name, baud_rate).await,
+            Err(_) => Err(format!("Invalid baud rate: {}", baud_rate)),
+        },
+        ["close", port_name] => simple_request(ControlRequest::ClosePort {
+            port_name: port_name.to_string(),
+        })
+        .await,
+        ["events"] => events(false).await,
+        ["events", "--json"] => events(true).await,
+        ["presets"] => presets().await,
+        ["set-preset", device_id, preset_id] => simple_request(ControlRequest::SetMappingPreset {
+            device_id: device_id.to_string(),
+            preset_id: preset_id.to_string(),
+        })
+        .await,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn connect() -> Result<ControlClient, String> {
+    ControlClient::connect()
+        .await
+        .map_err(|e| format!("Failed to connect to ardeck studio: {}", e))
+}
+
+async fn request(client: &mut ControlClient, request: ControlRequest) -> Result<ControlResponse, String> {
+    match client.request(&request).await {
+        Ok(ControlResponse::Error(e)) => Err(e),
+        Ok(response) => Ok(response),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn simple_request(control_request: ControlRequest) -> Result<(), String> {
+    let mut client = connect().await?;
+    request(&mut client, control_request).await?;
+
+    Ok(())
+}
+
+async fn ports() -> Result<(), String> {
+    // スタジオが起動していなければ、接続状態は表示しない
+    let connecting = match connect().await {
+        Ok(mut client) => match request(&mut client, ControlRequest::GetConnectingSerials).await? {
+            ControlResponse::ConnectingSerials(list) => Some(list),
+            _ => None,
+        },
+        Err(_) => None,
+    };
+
+    let ports = ArdeckCore::get_ports().map_err(|e| e.to_string())?;
+
+    for (device_id, port) in ports {
+        let state = match &connecting {
+            Some(list) if list.contains(&port.port_name) => "\topen",
+            Some(_) => "\tclosed",
+            None => "",
+        };
+
+        println!("{}\t{}{}", port.port_name, device_id, state);
+    }
+
+    Ok(())
+}
+
+async fn open(port_name: &str, baud_rate: u32) -> Result<(), String> {
+    simple_request(ControlRequest::OpenPort {
+        port_name: port_name.to_string(),
+        baud_rate,
+    })
+    .await
+}
+
+async fn presets() -> Result<(), String> {
+    let mut client = connect().await?;
+
+    if let ControlResponse::MappingList(list) =
+        request(&mut client, ControlRequest::GetMappingList).await?
+    {
+        for (uuid, name) in list {
+            println!("{}\t{}", uuid, name);
+        }
+    }
+
+    Ok(())
+}
+
+async fn events(json: bool) -> Result<(), String> {
+    let mut client = connect().await?;
+    request(&mut client, ControlRequest::Subscribe).await?;
+
+    loop {
+        match client.next_response().await.map_err(|e| e.to_string())? {
+            ControlResponse::Switch(event) => {
+                if json {
+                    println!("{}", serde_json::to_string(&event).unwrap());
+                } else {
+                    println!("{}", format_event(&event));
+                }
+            }
+            ControlResponse::Error(e) => return Err(e),
+            _ => (),
+        }
+    }
+}
+
+fn format_event(event: &ControlSwitchEvent) -> String {
+    let time = chrono::DateTime::from_timestamp_millis(event.switch.timestamp)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%H:%M:%S%.3f")
+                .to_string()
+        })
+        .unwrap_or_default();
+
+    format!(
+        "[{}] {} ({}) {:?} #{} = {}",
+        time,
+        event.device_id,
+        event.port_name,
+        event.switch.switch_type,
+        event.switch.switch_id,
+        event.switch.switch_state
+    )
+}
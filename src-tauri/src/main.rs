@@ -19,15 +19,12 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod ardeck_studio;
-mod service;
-
 use std::time::SystemTime;
 
+use ardeck::{ardeck_studio, service::dir::Directories};
 use fern::colors::ColoredLevelConfig;
-use service::dir::Directories;
 use tauri::{
-    CustomMenuItem, LogicalSize, Manager, Runtime, Size, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, Window, WindowBuilder
+    AppHandle, CustomMenuItem, LogicalSize, Manager, RunEvent, Runtime, Size, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, Window, WindowBuilder
 };
 use tokio::fs::{self, File};
 use window_shadows::set_shadow;
@@ -159,6 +156,16 @@ async fn main() {
 
     // print!("\x1B[2J\x1B[1;1H"); // ! コンソールをクリア
 
+    // --headless: ウィンドウを開かず、デバイスとプラグインだけを動かす
+    if std::env::args().any(|arg| arg == "--headless") {
+        ardeck_studio::headless::run().await;
+        return;
+    }
+
+    run_gui().await;
+}
+
+async fn run_gui() {
     // システムトレイアイコンの設定
     let show = CustomMenuItem::new("show".to_string(), "Show");
     let hide = CustomMenuItem::new("hide".to_string(), "Hide");
@@ -184,6 +191,26 @@ async fn main() {
                 app.package_info().version.to_string()
             );
 
+            // コントロールソケットを開けなくてもスタジオは使えるため、知らせるだけにする
+            let app_handle = app.app_handle();
+            tokio::spawn(async move {
+                if let Err(e) = ardeck_studio::control::start().await {
+                    log::error!("Failed to start control server: {}", e);
+                    tauri::api::dialog::message(
+                        app_handle.get_window("main").as_ref(),
+                        "ardeck studio",
+                        format!("ardeckctl is not available: {}", e),
+                    );
+                }
+            });
+
+            // SIGTERMを受け取ったら終了処理を行う
+            let app_handle = app.app_handle();
+            tokio::spawn(async move {
+                ardeck_studio::shutdown::wait_for_signal().await;
+                quit_app(app_handle);
+            });
+
             Ok(())
         })
         .system_tray(tray)
@@ -207,7 +234,7 @@ async fn main() {
                     window.hide().unwrap();
                 }
                 "quit" => {
-                    std::process::exit(0);
+                    quit_app(app.app_handle());
                 }
                 _ => {}
             },
@@ -217,6 +244,21 @@ async fn main() {
         .plugin(ardeck_studio::plugin::tauri::init().await)
         .plugin(ardeck_studio::settings::tauri::init())
         .invoke_handler(tauri::generate_handler![open_about])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // 最後のウィンドウが閉じられた時など
+            if let RunEvent::ExitRequested { api, .. } = event {
+                api.prevent_exit();
+                quit_app(app.app_handle());
+            }
+        });
+}
+
+// 終了処理を行ってからアプリを終了する
+fn quit_app<R: Runtime>(app: AppHandle<R>) {
+    tokio::spawn(async move {
+        ardeck_studio::shutdown::shutdown().await;
+        app.exit(0);
+    });
 }
@@ -46,10 +46,19 @@ impl Directories {
         Ok(Self::get_confing_dir()?.join("logs"))
     }
 
+    /// プラグインの出力のログ。アプリのログと分けて管理する
+    pub fn get_plugin_log_dir() -> std::io::Result<PathBuf> {
+        Ok(Self::get_log_dir()?.join("plugins"))
+    }
+
     pub fn get_settings_dir() -> std::io::Result<PathBuf> {
         Ok(Self::get_confing_dir()?.join("config"))
     }
 
+    pub fn get_plugin_settings_dir() -> std::io::Result<PathBuf> {
+        Ok(Self::get_settings_dir()?.join("plugins"))
+    }
+
     pub fn get_plugin_dir() -> std::io::Result<PathBuf> {
         Ok(Self::get_confing_dir()?.join("plugins"))
     }
@@ -306,6 +306,7 @@ export default function ActionMappingForm() {
                                                 return {
                                                     ...prev,
                                                     actionId: newValue,
+                                                    parameters: {},
                                                 };
                                             });
 
@@ -315,6 +316,7 @@ export default function ActionMappingForm() {
                                             mapping[i] = {
                                                 ...mapping[i],
                                                 actionId: newValue,
+                                                parameters: {},
                                             };
                                             return { ...prev, mapping };
                                         }
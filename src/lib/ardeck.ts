@@ -36,19 +36,24 @@ export type SwitchType = (typeof SwitchType)[keyof typeof SwitchType];
  * @property switchId - スイッチの識別子
  * @property pluginId - プラグインの識別子
  * @property actionId - アクションの識別子
+ * @property parameters - アクションのパラメーター
  */
 export type ActionMap = {
     switchType: SwitchType;
     switchId: number;
     pluginId: string;
     actionId: string;
+    parameters: ActionParameterValues;
 };
 
+export type ActionParameterValues = Record<string, unknown>;
+
 export const defaultActionMap: ActionMap = {
     switchType: SwitchType.Digital,
     switchId: 0,
     pluginId: "",
     actionId: "",
+    parameters: {},
 };
 
 export type SerialPortInfo = {
@@ -81,6 +86,7 @@ export type SwitchInfo = {
 export type ActionTarget = {
     actionId: string;
     pluginId: string;
+    parameters: ActionParameterValues;
 };
 
 export type Action = {
@@ -118,6 +124,16 @@ export type ArdeckProfileConfigItem = {
     mappingPreset?: string;
 };
 
+/**
+ * プラグインが作った仮想デバイス
+ */
+export type VirtualDeviceInfo = {
+    portName: string;
+    deviceId: string;
+    name: string | null;
+    pluginId: string;
+};
+
 export const BaudRateList = [
     150,
     200,
@@ -0,0 +1,30 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or 
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful, 
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the 
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+
+/**
+ * tauriのコマンドが返すエラー
+ * @property code - エラーの種類を表す安定したコード (例: "device.port_not_found")
+ * @property message - エラーの内容
+ * @property details - 原因となったエラーなどの追加情報
+ */
+export type CommandError = {
+    code: string;
+    message: string;
+    details: string | null;
+};
@@ -16,6 +16,29 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+export type PluginStatus =
+    | "starting"
+    | "connected"
+    | "unresponsive"
+    | "crashed"
+    | "stopped";
+
+export type PluginRuntime = "node" | "bun" | "python";
+
+export type PluginPermission =
+    | "state.read"
+    | "device.write"
+    | "device.virtual"
+    | "preset.switch"
+    | "notification.show";
+
+export type PluginQueueMetrics = {
+    queued: number,
+    flushed: number,
+    overflowed: number,
+    expired: number,
+}
+
 export type PluginManifestJSON = {
     name: string,
     version: string,
@@ -23,12 +46,79 @@ export type PluginManifestJSON = {
     description?: string,
     author: string,
     main: string,
+    runtime: PluginRuntime | null,
+    permissions: PluginPermission[],
+    status: PluginStatus,
+    enabled: boolean,
+    grantedPermissions: PluginPermission[],
+    /** ユーザーの確認を待っている権限。空でなければ起動していない */
+    pendingPermissions: PluginPermission[],
+    queue: PluginQueueMetrics,
+}
+
+export type PluginStatusEvent = {
+    pluginId: string,
+    status: PluginStatus,
+}
+
+export type PluginNotification = {
+    pluginId: string,
+    title: string,
+    body: string | null,
+}
+
+export type PluginActionFailure = {
+    pluginId: string,
+    actionId: string,
+    message: string,
+    timestamp: number,
+}
+
+export type PluginOutputLine = {
+    stream: "stdout" | "stderr",
+    line: string,
+    timestamp: number,
+}
+
+export type PluginLoadError = {
+    dir: string,
+    pluginId: string | null,
+    code: string,
+    message: string,
+    details: string | null,
+    file: string | null,
+    line: number | null,
+    column: number | null,
+}
+
+export type PluginConfig = Record<string, unknown>;
+
+export type PluginConfigEvent = {
+    pluginId: string,
+    config: PluginConfig,
+}
+
+export type ActionParameterType = "string" | "number" | "integer" | "boolean";
+
+export type ActionParameter = {
+    name: string,
+    type: ActionParameterType,
+    title?: string,
+    description?: string,
+    required: boolean,
+    default?: unknown,
+    enum?: unknown[],
+    minimum?: number,
+    maximum?: number,
 }
 
 export type PluginAction = {
     name: string,
     id: string,
     description?: string,
+    parameters: ActionParameter[],
+    // プラグインが無効な場合はfalse
+    available: boolean,
 }
 
 export type PluginActionList = PluginAction[];
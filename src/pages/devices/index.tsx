@@ -18,7 +18,11 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 import { useEffect, useState } from "react";
 import BackToRoot from "../_component/back_to_root";
-import { ArdeckProfileConfigItem, SerialPortInfo } from "../../lib/ardeck";
+import {
+    ArdeckProfileConfigItem,
+    SerialPortInfo,
+    VirtualDeviceInfo,
+} from "../../lib/ardeck";
 import { invoke } from "../../tauri/invoke";
 import { listen } from "../../tauri/listen";
 import Popup from "../../component/popup";
@@ -33,6 +37,9 @@ export default function Devices() {
     >([]);
 
     const [connectingDevice, setConnectingDevice] = useState<string[]>();
+    const [virtualDevices, setVirtualDevices] = useState<VirtualDeviceInfo[]>(
+        [],
+    );
 
     const questionDeviceName = (): string => {
         const name = prompt("Device name");
@@ -94,6 +101,7 @@ export default function Devices() {
 
         const refreshConnectingDevice = async () => {
             setConnectingDevice(await invoke.ardeck.getConnectingSerials());
+            setVirtualDevices(await invoke.ardeck.getVirtualDevices());
         };
 
         const onOpenHandle = listen.onOpenSerial(refreshConnectingDevice);
@@ -212,6 +220,32 @@ export default function Devices() {
                     );
                 })}
             </div>
+            <h2 className="text-xl font-bold">Virtual</h2>
+            <div className="flex gap-2">
+                {virtualDevices.map((device) => {
+                    const profile = deviceProfileList.find(
+                        (profile) => profile[0] === device.deviceId,
+                    );
+
+                    return (
+                        <div
+                            className="bg-bg-secondary flex w-64 flex-col rounded-md p-4 *:overflow-hidden *:text-nowrap *:text-ellipsis"
+                            key={device.deviceId}
+                        >
+                            <div className="text-xl font-bold">
+                                {profile?.[1] || device.name || device.deviceId}
+                            </div>
+                            <div>plugin: {device.pluginId}</div>
+                            <Link
+                                className="input bg-bg-tertiary mt-2 rounded-sm text-center"
+                                to={encodeURIComponent(device.deviceId)}
+                            >
+                                Edit
+                            </Link>
+                        </div>
+                    );
+                })}
+            </div>
             {/* <BackToRoot>Back to root</BackToRoot> */}
         </div>
     );
@@ -22,6 +22,7 @@ import { VscArrowLeft, VscTrash } from "react-icons/vsc";
 import { useEffect, useState } from "react";
 import { defaultMappingPreset, MappingPreset } from "../../lib/settings";
 import { invoke } from "../../tauri/invoke";
+import { listen } from "../../tauri/listen";
 import LoadingScreen from "../_component/loading/legacy";
 import Input from "../_component/form/Input";
 import Button from "../_component/Button";
@@ -113,8 +114,6 @@ export default function MappingSetting() {
 
     // プラグイン一覧を取得
     useEffect(() => {
-        const pluginActions: PluginActions[] = [];
-
         const getPluginManifestList = async () => {
             const list = await invoke.plugin.getPluginManifests();
         };
@@ -123,6 +122,7 @@ export default function MappingSetting() {
             const list = await invoke.plugin.getPluginActions(plugin_id!);
         };
         const initPluginActions = async () => {
+            const pluginActions: PluginActions[] = [];
             const list = await invoke.plugin.getPluginManifests();
             for (const manifest of list) {
                 const actions = await invoke.plugin.getPluginActions(
@@ -133,6 +133,13 @@ export default function MappingSetting() {
             setPlugins(pluginActions);
         };
         initPluginActions();
+
+        // プラグインの追加や再読み込みで、アクションの一覧を更新する
+        const onPluginList = listen.onPluginList(initPluginActions);
+
+        return () => {
+            onPluginList.then((unlisten) => unlisten());
+        };
     }, []);
 
     return (
@@ -326,6 +333,7 @@ export default function MappingSetting() {
                                                 m.preset = {
                                                     ...m.preset,
                                                     actionId: e.target.value,
+                                                    parameters: {},
                                                 };
                                                 return m;
                                             }
@@ -343,6 +351,7 @@ export default function MappingSetting() {
                                     return (
                                         <Button
                                             key={`${plugin.manifest.id}-${action.id}`}
+                                            disabled={!action.available}
                                             onClick={() =>
                                                 setModalParams((prev) => {
                                                     if (prev) {
@@ -354,6 +363,7 @@ export default function MappingSetting() {
                                                                 plugin.manifest
                                                                     .id,
                                                             actionId: action.id,
+                                                            parameters: {},
                                                         };
                                                         return m;
                                                     }
@@ -362,6 +372,7 @@ export default function MappingSetting() {
                                             }
                                         >
                                             {plugin.manifest.name} : {action.name}
+                                            {!action.available && " (disabled)"}
                                         </Button>
                                     );
                                 });
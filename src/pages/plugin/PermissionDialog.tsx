@@ -0,0 +1,101 @@
+/*
+Ardeck studio - The ardeck command mapping software.
+Copyright (C) 2024 Project Ardeck
+
+This program is free software; you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or 
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful, 
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the 
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+import { useEffect, useState } from "react";
+import { PluginManifestJSON, PluginPermission } from "../../lib/plugin";
+import { invoke } from "../../tauri/invoke";
+import { ModalWindow, ModalWindowContainer } from "../_component/ModalWindow";
+import Button from "../_component/Button";
+
+/**
+ * プラグインが宣言した権限を、ユーザーに一つずつ確認するダイアログ
+ * チェックしたものだけを許可し、残りは許可しなかったものとして保存する
+ * 拒否した場合は何も許可せず、プラグインを無効にする
+ */
+export default function PermissionDialog(props: {
+    plugin: PluginManifestJSON | null;
+    onClose: () => void;
+}) {
+    const { plugin, onClose } = props;
+    const [selected, setSelected] = useState<PluginPermission[]>([]);
+
+    // 開くたびに、許可済みの権限だけをチェックした状態に戻す
+    useEffect(() => {
+        setSelected(plugin ? plugin.grantedPermissions : []);
+    }, [plugin]);
+
+    const toggle = (permission: PluginPermission, checked: boolean) => {
+        setSelected((prev) =>
+            checked
+                ? [...prev, permission]
+                : prev.filter((p) => p !== permission),
+        );
+    };
+
+    const approve = async () => {
+        await invoke.plugin.setPluginPermissions(plugin!.id, selected);
+        onClose();
+    };
+
+    const deny = async () => {
+        await invoke.plugin.setPluginPermissions(plugin!.id, []);
+        await invoke.plugin.setPluginEnabled(plugin!.id, false);
+        onClose();
+    };
+
+    return (
+        <ModalWindow isOpen={plugin !== null}>
+            {plugin && (
+                <ModalWindowContainer className="flex flex-col gap-4">
+                    <div>{plugin.name} requests permissions</div>
+                    <div className="flex flex-col gap-2">
+                        {plugin.permissions.map((permission) => (
+                            <label
+                                className="flex items-center gap-2"
+                                key={permission}
+                            >
+                                <input
+                                    type="checkbox"
+                                    checked={selected.includes(permission)}
+                                    onChange={(e) =>
+                                        toggle(permission, e.target.checked)
+                                    }
+                                />
+                                {permission}
+                            </label>
+                        ))}
+                    </div>
+                    <div className="flex w-full gap-2">
+                        <Button
+                            className="w-full flex-1"
+                            onClick={approve}
+                        >
+                            approve
+                        </Button>
+                        <Button
+                            className="hover:bg-accent-negative w-16"
+                            onClick={deny}
+                        >
+                            deny
+                        </Button>
+                    </div>
+                </ModalWindowContainer>
+            )}
+        </ModalWindow>
+    );
+}
@@ -21,20 +21,55 @@ import LoadingScreen from "../_component/loading/legacy";
 import BackToPrev from "../_component/back_to_prev";
 import { VscArrowLeft } from "react-icons/vsc";
 import { useEffect, useState } from "react";
-import { PluginActionList } from "../../lib/plugin";
+import {
+    PluginActionFailure,
+    PluginActionList,
+    PluginOutputLine,
+} from "../../lib/plugin";
 import { invoke } from "../../tauri/invoke";
+import { listen } from "../../tauri/listen";
 
 export default function PluginActions() {
     const { plugin_id } = useParams();
 
     const [pluginActions, setPluginActions] = useState<PluginActionList>([]);
+    const [actionFailures, setActionFailures] = useState<
+        Array<PluginActionFailure>
+    >([]);
+    const [output, setOutput] = useState<Array<PluginOutputLine>>([]);
+
+    const getOutput = async () => {
+        const lines = await invoke.plugin.getPluginOutput(plugin_id!);
+        setOutput(lines);
+    };
 
     useEffect(() => {
         const getPluginActions = async () => {
             const list = await invoke.plugin.getPluginActions(plugin_id!);
             setPluginActions(list);
         };
+        const getActionFailures = async () => {
+            const list = await invoke.plugin.getPluginActionFailures(
+                plugin_id!,
+            );
+            setActionFailures(list);
+        };
         getPluginActions();
+        getActionFailures();
+        getOutput();
+
+        const onActionFailed = listen.onPluginActionFailed((failure) => {
+            if (failure.pluginId === plugin_id) {
+                getActionFailures();
+            }
+        });
+        // セッション中に登録されたアクションを反映する
+        const onPluginList = listen.onPluginList(getPluginActions);
+
+        return () => {
+            onActionFailed.then((unlisten) => unlisten());
+            onPluginList.then((unlisten) => unlisten());
+        };
     }, []);
 
     return (
@@ -66,6 +101,50 @@ export default function PluginActions() {
                     );
                 })}
             </div>
+            {actionFailures.length > 0 && (
+                <>
+                    <h2 className="text-xl font-bold">Recent failures</h2>
+                    <div className="flex flex-col gap-2">
+                        {[...actionFailures].reverse().map((failure) => {
+                            const action = pluginActions.find(
+                                (a) => a.id === failure.actionId,
+                            );
+                            return (
+                                <div
+                                    className="bg-bg-secondary flex w-full items-center justify-between rounded-md px-4 py-2 text-sm"
+                                    key={`${failure.timestamp}-${failure.actionId}`}
+                                >
+                                    <div>
+                                        {action?.name ?? failure.actionId}:{" "}
+                                        {failure.message}
+                                    </div>
+                                    <div>
+                                        {new Date(
+                                            failure.timestamp,
+                                        ).toLocaleTimeString()}
+                                    </div>
+                                </div>
+                            );
+                        })}
+                    </div>
+                </>
+            )}
+            <div className="flex items-center justify-between">
+                <h2 className="text-xl font-bold">Output</h2>
+                <button onClick={getOutput}>Refresh</button>
+            </div>
+            <pre className="bg-bg-secondary max-h-96 overflow-auto rounded-md px-4 py-2 text-sm">
+                {output.map((line, i) => (
+                    <div
+                        className={
+                            line.stream === "stderr" ? "text-red-500" : ""
+                        }
+                        key={i}
+                    >
+                        {line.line}
+                    </div>
+                ))}
+            </pre>
         </div>
     );
 }
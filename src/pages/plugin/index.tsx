@@ -17,42 +17,144 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
 import { useEffect, useState } from "react";
+import { open } from "@tauri-apps/api/dialog";
 import { PluginManifestJSON } from "../../lib/plugin";
+import { CommandError } from "../../lib/error";
 import { invoke } from "../../tauri/invoke";
+import { listen } from "../../tauri/listen";
 import { Link } from "react-router";
+import Button from "../_component/Button";
+import PermissionDialog from "./PermissionDialog";
 
 export default function Plugin() {
     const [pluginManifestList, setPluginManifestList] = useState<
         Array<PluginManifestJSON>
     >([]);
+    // 権限を確認しているプラグイン
+    const [reviewing, setReviewing] = useState<PluginManifestJSON | null>(
+        null,
+    );
+    const [installError, setInstallError] = useState<CommandError | null>(
+        null,
+    );
+
+    const getPluginManifestList = async () => {
+        const list = await invoke.plugin.getPluginManifests();
+        setPluginManifestList(list);
+    };
 
     useEffect(() => {
-        const getPluginManifestList = async () => {
-            const list = await invoke.plugin.getPluginManifests();
-            setPluginManifestList(list);
-        };
         getPluginManifestList();
+
+        const onPluginList = listen.onPluginList(getPluginManifestList);
+        const onPluginStatus = listen.onPluginStatus(getPluginManifestList);
+
+        return () => {
+            onPluginList.then((unlisten) => unlisten());
+            onPluginStatus.then((unlisten) => unlisten());
+        };
     }, []);
 
+    const toggleEnabled = async (id: string, enabled: boolean) => {
+        await invoke.plugin.setPluginEnabled(id, enabled);
+        await getPluginManifestList();
+    };
+
+    // アーカイブを選んでインストールし、確認が必要な権限があれば確認する
+    const installPlugin = async () => {
+        const archivePath = await open({
+            filters: [{ name: "Plugin", extensions: ["zip", "gz", "tgz"] }],
+        });
+        if (typeof archivePath !== "string") return;
+
+        try {
+            const plugin = await invoke.plugin.installPlugin(archivePath);
+            setInstallError(null);
+            if (plugin.pendingPermissions.length > 0) setReviewing(plugin);
+        } catch (e) {
+            setInstallError(e as CommandError);
+        }
+        await getPluginManifestList();
+    };
+
+    const closeReview = async () => {
+        setReviewing(null);
+        await getPluginManifestList();
+    };
+
     return (
         <div>
-            <h1 className="pagetitle mb-4">Plugin</h1>
+            <div className="mb-4 flex items-center justify-between">
+                <h1 className="pagetitle">Plugin</h1>
+                <Button onClick={installPlugin}>Install</Button>
+            </div>
+            {installError && (
+                <div className="mb-4 text-sm text-red-500">
+                    {installError.message}
+                </div>
+            )}
             <div className="flex flex-col gap-2">
-                {pluginManifestList.map(
-                    ({ name, id, version, description }) => {
-                        return (
-                            <Link
-                                className="bg-bg-secondary flex justify-between rounded-md px-4 py-2"
-                                key={id}
-                                to={`/plugin/${id}`}
-                            >
-                                <div>{name}</div>
-                                <div>{version}</div>
-                            </Link>
-                        );
-                    },
-                )}
+                {pluginManifestList.map((plugin) => {
+                    const { name, id, version, enabled, status, queue } =
+                        plugin;
+                    const dropped = queue.overflowed + queue.expired;
+                    const { pendingPermissions } = plugin;
+
+                    return (
+                        <div className="flex flex-col gap-1" key={id}>
+                            <div className="bg-bg-secondary flex items-center justify-between gap-4 rounded-md px-4 py-2">
+                                <Link
+                                    className="flex flex-1 justify-between"
+                                    to={`/plugin/${id}`}
+                                >
+                                    <div>{name}</div>
+                                    <div className="flex gap-4">
+                                        <div
+                                            className={
+                                                status === "unresponsive" ||
+                                                status === "crashed"
+                                                    ? "text-red-500"
+                                                    : ""
+                                            }
+                                        >
+                                            {status}
+                                        </div>
+                                        {dropped > 0 && (
+                                            <div
+                                                className="text-yellow-500"
+                                                title={`overflowed: ${queue.overflowed}, expired: ${queue.expired}`}
+                                            >
+                                                dropped {dropped}
+                                            </div>
+                                        )}
+                                        <div>{version}</div>
+                                    </div>
+                                </Link>
+                                <input
+                                    type="checkbox"
+                                    checked={enabled}
+                                    onChange={(e) =>
+                                        toggleEnabled(id, e.target.checked)
+                                    }
+                                />
+                            </div>
+                            {pendingPermissions.length > 0 && (
+                                <div className="flex items-center justify-between gap-4 px-4 text-sm">
+                                    <div>
+                                        Requests: {pendingPermissions.join(", ")}
+                                    </div>
+                                    <button
+                                        onClick={() => setReviewing(plugin)}
+                                    >
+                                        Review
+                                    </button>
+                                </div>
+                            )}
+                        </div>
+                    );
+                })}
             </div>
+            <PermissionDialog plugin={reviewing} onClose={closeReview} />
         </div>
     );
 }
@@ -17,9 +17,21 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
 import { invoke as tauriInvoke } from "@tauri-apps/api";
-import { ArdeckProfileConfigItem, SerialPortInfo } from "../lib/ardeck";
+import {
+    ArdeckProfileConfigItem,
+    SerialPortInfo,
+    VirtualDeviceInfo,
+} from "../lib/ardeck";
 import { MappingPreset } from "../lib/settings";
-import { PluginActionList, PluginManifestJSON } from "../lib/plugin";
+import {
+    PluginActionFailure,
+    PluginActionList,
+    PluginConfig,
+    PluginLoadError,
+    PluginManifestJSON,
+    PluginOutputLine,
+    PluginPermission,
+} from "../lib/plugin";
 
 // TODO: error handling
 export const invoke = {
@@ -88,7 +100,67 @@ export const invoke = {
             return await tauriInvoke("plugin:ardeck-plugin|get_plugin_actions", {
                 pluginId,
             })
-        }
+        },
+        async getPluginActionFailures(
+            pluginId: string,
+        ): Promise<Array<PluginActionFailure>> {
+            return await tauriInvoke(
+                "plugin:ardeck-plugin|get_plugin_action_failures",
+                { pluginId },
+            );
+        },
+        async getPluginOutput(
+            pluginId: string,
+        ): Promise<Array<PluginOutputLine>> {
+            return await tauriInvoke("plugin:ardeck-plugin|get_plugin_output", {
+                pluginId,
+            });
+        },
+        async setPluginEnabled(
+            pluginId: string,
+            enabled: boolean,
+        ): Promise<PluginManifestJSON> {
+            return await tauriInvoke("plugin:ardeck-plugin|set_plugin_enabled", {
+                pluginId,
+                enabled,
+            });
+        },
+        async setPluginPermissions(
+            pluginId: string,
+            permissions: PluginPermission[],
+        ): Promise<PluginManifestJSON> {
+            return await tauriInvoke(
+                "plugin:ardeck-plugin|set_plugin_permissions",
+                { pluginId, permissions },
+            );
+        },
+        async getPluginErrors(): Promise<Array<PluginLoadError>> {
+            return await tauriInvoke("plugin:ardeck-plugin|get_plugin_errors");
+        },
+        async getPluginConfig(pluginId: string): Promise<PluginConfig> {
+            return await tauriInvoke("plugin:ardeck-plugin|get_plugin_config", {
+                pluginId,
+            });
+        },
+        async setPluginConfig(
+            pluginId: string,
+            config: PluginConfig,
+        ): Promise<undefined> {
+            return await tauriInvoke("plugin:ardeck-plugin|set_plugin_config", {
+                pluginId,
+                config,
+            });
+        },
+        async installPlugin(archivePath: string): Promise<PluginManifestJSON> {
+            return await tauriInvoke("plugin:ardeck-plugin|install_plugin", {
+                archivePath,
+            });
+        },
+        async uninstallPlugin(pluginId: string): Promise<undefined> {
+            return await tauriInvoke("plugin:ardeck-plugin|uninstall_plugin", {
+                pluginId,
+            });
+        },
     },
     ardeck: {
         async openPort(portName: string, baudRate: number): Promise<undefined> {
@@ -106,6 +178,9 @@ export const invoke = {
         async getPorts(): Promise<Array<[string, SerialPortInfo]>> {
             return await tauriInvoke("plugin:ardeck|get_ports");
         },
+        async getVirtualDevices(): Promise<Array<VirtualDeviceInfo>> {
+            return await tauriInvoke("plugin:ardeck|get_virtual_devices");
+        },
     },
     openWindow: {
         async about() {
@@ -18,6 +18,12 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 import { listen as _listen, UnlistenFn } from "@tauri-apps/api/event";
 import { SerialPortInfo } from "../lib/ardeck";
+import {
+    PluginActionFailure,
+    PluginConfigEvent,
+    PluginNotification,
+    PluginStatusEvent,
+} from "../lib/plugin";
 
 export const listen = {
     async onPorts(
@@ -42,4 +48,37 @@ export const listen = {
             callback(e.payload as string);
         });
     },
+    async onPluginStatus(
+        callback: (payload: PluginStatusEvent) => void,
+    ): Promise<UnlistenFn> {
+        return _listen("on-plugin-status", (e) => {
+            callback(e.payload as PluginStatusEvent);
+        });
+    },
+    async onPluginNotification(
+        callback: (payload: PluginNotification) => void,
+    ): Promise<UnlistenFn> {
+        return _listen("on-plugin-notification", (e) => {
+            callback(e.payload as PluginNotification);
+        });
+    },
+    async onPluginConfig(
+        callback: (payload: PluginConfigEvent) => void,
+    ): Promise<UnlistenFn> {
+        return _listen("on-plugin-config", (e) => {
+            callback(e.payload as PluginConfigEvent);
+        });
+    },
+    async onPluginList(callback: () => void): Promise<UnlistenFn> {
+        return _listen("on-plugin-list", () => {
+            callback();
+        });
+    },
+    async onPluginActionFailed(
+        callback: (payload: PluginActionFailure) => void,
+    ): Promise<UnlistenFn> {
+        return _listen("on-plugin-action-failed", (e) => {
+            callback(e.payload as PluginActionFailure);
+        });
+    },
 };